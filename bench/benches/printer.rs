@@ -28,9 +28,11 @@ fn large_file(c: &mut Criterion) {
     c.bench_function("printer::bat", |b| {
         b.iter(|| {
             let _gag = Gag::stdout().unwrap();
-            let mut opts = PrinterOptions::default();
-            opts.color_support = TermColorSupport::True;
-            opts.term_width = 80;
+            let opts = PrinterOptions {
+                color_support: TermColorSupport::True,
+                term_width: 80,
+                ..Default::default()
+            };
             let printer = BatPrinter::new(opts);
             for file in files.clone().into_iter() {
                 printer.print(file).unwrap();
@@ -41,9 +43,11 @@ fn large_file(c: &mut Criterion) {
     c.bench_function("printer::syntect", |b| {
         b.iter(|| {
             let _gag = Gag::stdout().unwrap();
-            let mut opts = PrinterOptions::default();
-            opts.color_support = TermColorSupport::True;
-            opts.term_width = 80;
+            let opts = PrinterOptions {
+                color_support: TermColorSupport::True,
+                term_width: 80,
+                ..Default::default()
+            };
             let printer = SyntectPrinter::with_stdout(opts).unwrap();
             files
                 .clone()
@@ -18,14 +18,8 @@ impl Printer for DummyPrinter {
 #[inline]
 fn run_ripgrep(pat: &str, path: &Path) -> bool {
     let mut config = ripgrep::Config::new(3, 6);
-    config.no_ignore(true);
-    ripgrep::grep(
-        DummyPrinter,
-        pat,
-        Some(iter::once(path.as_os_str())),
-        config,
-    )
-    .unwrap()
+    config.pattern(pat).no_ignore(true);
+    ripgrep::grep(DummyPrinter, Some(iter::once(path)), config).unwrap()
 }
 
 fn bench(c: &mut Criterion) {
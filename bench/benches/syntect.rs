@@ -6,11 +6,13 @@ use hgrep::syntect::{LockableWrite, SyntectAssets, SyntectPrinter};
 use hgrep_bench::node_modules_path;
 use hgrep_bench::read_package_lock_json;
 use rayon::prelude::*;
+use std::env;
 use std::io;
 use std::io::Write;
 use std::iter;
 use std::mem;
 use std::path::Path;
+use std::process;
 use std::sync::{Mutex, MutexGuard};
 use std::{cmp, fs};
 
@@ -34,10 +36,11 @@ impl<'a> LockableWrite<'a> for Sink {
 }
 
 fn get_opts() -> PrinterOptions<'static> {
-    let mut opts = PrinterOptions::default();
-    opts.color_support = TermColorSupport::True;
-    opts.term_width = 80;
-    opts
+    PrinterOptions {
+        color_support: TermColorSupport::True,
+        term_width: 80,
+        ..Default::default()
+    }
 }
 
 fn create_files_for_contents(contents: String, path: &Path, per_lines: usize) -> Vec<File> {
@@ -57,12 +60,40 @@ fn create_files_for_contents(contents: String, path: &Path, per_lines: usize) ->
 }
 
 fn load_assets(c: &mut Criterion) {
+    // The decompressed syntax set is cached on the first iteration
     c.bench_function("syntect::load-assets", |b| {
         b.iter(|| {
             let assets = SyntectAssets::load(None).unwrap();
             black_box(assets)
         })
     });
+
+    // Syntax set is not loaded until the first file is printed
+    c.bench_function("syntect::new-printer", |b| {
+        b.iter(|| {
+            let printer = SyntectPrinter::new(Sink::default(), get_opts()).unwrap();
+            black_box(printer)
+        })
+    });
+
+    let cache = env::temp_dir().join(format!("hgrep-bench-cache-{}", process::id()));
+    env::set_var("HGREP_CACHE_PATH", &cache);
+    c.bench_function("syntect::load-assets-first-run", |b| {
+        b.iter(|| {
+            let _ = fs::remove_dir_all(&cache);
+            let assets = SyntectAssets::load(None).unwrap();
+            black_box(assets)
+        })
+    });
+    env::set_var("HGREP_CACHE_PATH", "");
+    c.bench_function("syntect::load-assets-no-cache", |b| {
+        b.iter(|| {
+            let assets = SyntectAssets::load(None).unwrap();
+            black_box(assets)
+        })
+    });
+    env::remove_var("HGREP_CACHE_PATH");
+    let _ = fs::remove_dir_all(&cache);
 }
 
 fn print_files(c: &mut Criterion) {
@@ -108,8 +139,8 @@ fn with_ripgrep(c: &mut Criterion) {
         let sink = Sink(Mutex::new(vec![]));
         let printer = SyntectPrinter::new(sink, opts).unwrap();
         let mut config = ripgrep::Config::new(3, 6);
-        config.no_ignore(true);
-        ripgrep::grep(printer, pat, Some(iter::once(dir.as_os_str())), config).unwrap()
+        config.pattern(pat).no_ignore(true);
+        ripgrep::grep(printer, Some(iter::once(dir)), config).unwrap()
     }
 
     let node_modules = node_modules_path();
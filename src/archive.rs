@@ -0,0 +1,246 @@
+use grep_cli::DecompressionReader;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Separator between the path of an archive and the path of its member like `libs/app.jar!/META-INF/application.yml`
+pub const SEPARATOR: &str = "!/";
+
+const ZIP_EXTENSIONS: &[&str] = &[".zip", ".jar", ".war", ".ear"];
+// Compressed tar archives are decompressed by external commands in the same way as `--search-zip`
+const TAR_EXTENSIONS: &[&str] = &[
+    ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".tar.zst",
+];
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Zip,
+    Tar,
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if ZIP_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+        Some(Kind::Zip)
+    } else if TAR_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+        Some(Kind::Tar)
+    } else {
+        None
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+// Path to show a member of the archive. The member path is always separated with `/` in archives
+pub fn member_path(archive: &Path, member: &Path) -> PathBuf {
+    let mut path = OsString::from(archive);
+    path.push(SEPARATOR);
+    path.push(member);
+    PathBuf::from(path)
+}
+
+// Split the path returned from `member_path` into the archive path and the member path. `None` is returned when the
+// path does not point to a member of an archive
+pub fn split_path(path: &Path) -> Option<(&Path, &Path)> {
+    let s = path.to_str()?;
+    s.match_indices(SEPARATOR).find_map(|(idx, sep)| {
+        let archive = Path::new(&s[..idx]);
+        let member = &s[idx + sep.len()..];
+        if is_archive(archive) && !member.is_empty() {
+            Some((archive, Path::new(member)))
+        } else {
+            None
+        }
+    })
+}
+
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+// Call the callback with the path and the contents of each file in the archive. Directories and links are skipped.
+// Archives nested in the archive are not searched
+pub fn for_each_member<F>(path: &Path, mut f: F) -> anyhow::Result<()>
+where
+    F: FnMut(&Path, &[u8]) -> anyhow::Result<()>,
+{
+    let mut contents = vec![];
+    match kind(path) {
+        Some(Kind::Zip) => {
+            let mut zip = zip::ZipArchive::new(fs::File::open(path)?).map_err(zip_error)?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i).map_err(zip_error)?;
+                if !file.is_file() {
+                    continue;
+                }
+                let member = match file.enclosed_name() {
+                    Some(p) => p.to_path_buf(),
+                    None => continue, // Skip unsafe path like `../foo`
+                };
+                contents.clear();
+                file.read_to_end(&mut contents)?;
+                f(&member, &contents)?;
+            }
+        }
+        Some(Kind::Tar) => {
+            let mut tar = tar::Archive::new(DecompressionReader::new(path)?);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let member = entry.path()?.into_owned();
+                contents.clear();
+                entry.read_to_end(&mut contents)?;
+                f(&member, &contents)?;
+            }
+        }
+        None => anyhow::bail!("{:?} is not an archive", path),
+    }
+    Ok(())
+}
+
+// Read the contents of the member in the archive
+pub fn read_member(archive: &Path, member: &Path) -> io::Result<Vec<u8>> {
+    let not_found = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{:?} was not found in archive {:?}", member, archive),
+        )
+    };
+    let mut contents = vec![];
+    match kind(archive) {
+        Some(Kind::Zip) => {
+            let mut zip = zip::ZipArchive::new(fs::File::open(archive)?).map_err(zip_error)?;
+            let name = member.to_str().ok_or_else(not_found)?;
+            let mut file = match zip.by_name(name) {
+                Ok(file) => file,
+                Err(zip::result::ZipError::FileNotFound) => return Err(not_found()),
+                Err(err) => return Err(zip_error(err)),
+            };
+            file.read_to_end(&mut contents)?;
+        }
+        Some(Kind::Tar) => {
+            let mut tar = tar::Archive::new(DecompressionReader::new(archive)?);
+            let mut entries = tar.entries()?;
+            let mut entry = loop {
+                let entry = entries.next().ok_or_else(not_found)??;
+                if entry.header().entry_type().is_file() && entry.path()? == member {
+                    break entry;
+                }
+            };
+            entry.read_to_end(&mut contents)?;
+        }
+        None => return Err(not_found()),
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new("testdata").join("archive").join(name)
+    }
+
+    #[test]
+    fn test_is_archive() {
+        let tests = [
+            ("app.jar", true),
+            ("dir/bundle.zip", true),
+            ("app.war", true),
+            ("APP.JAR", true),
+            ("bundle.tar", true),
+            ("bundle.tar.gz", true),
+            ("bundle.tgz", true),
+            ("bundle.tar.zst", true),
+            ("access.log.gz", false),
+            ("main.rs", false),
+            ("jar", false),
+        ];
+        for (path, want) in tests.iter() {
+            assert_eq!(is_archive(Path::new(path)), *want, "path={:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_split_path() {
+        let path = member_path(
+            Path::new("libs/app.jar"),
+            Path::new("META-INF/application.yml"),
+        );
+        assert_eq!(path, Path::new("libs/app.jar!/META-INF/application.yml"));
+        assert_eq!(
+            split_path(&path),
+            Some((
+                Path::new("libs/app.jar"),
+                Path::new("META-INF/application.yml"),
+            )),
+        );
+
+        let path = Path::new("dir!/bundle.tar.gz!/src/main.rs");
+        assert_eq!(
+            split_path(path),
+            Some((Path::new("dir!/bundle.tar.gz"), Path::new("src/main.rs"))),
+        );
+
+        for path in ["src/main.rs", "dir!/main.rs", "app.jar!/", "app.jar"] {
+            assert_eq!(split_path(Path::new(path)), None, "path={:?}", path);
+        }
+    }
+
+    fn collect_members(path: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut members = vec![];
+        for_each_member(path, |member, contents| {
+            members.push((member.to_path_buf(), contents.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_for_each_member() {
+        let want = vec![
+            (
+                PathBuf::from("META-INF/application.yml"),
+                fs::read(testdata("application.yml")).unwrap(),
+            ),
+            (
+                PathBuf::from("src/main.rs"),
+                fs::read(testdata("main.rs")).unwrap(),
+            ),
+        ];
+        for name in ["app.jar", "bundle.tar", "bundle.tar.gz"] {
+            let path = testdata(name);
+            let matcher = grep_cli::DecompressionMatcher::new();
+            if name.ends_with(".gz") && matcher.command(&path).is_none() {
+                continue; // gzip command is not installed
+            }
+            assert_eq!(collect_members(&path), want, "archive={:?}", name);
+        }
+
+        assert!(for_each_member(&testdata("main.rs"), |_, _| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_read_member() {
+        let want = fs::read(testdata("application.yml")).unwrap();
+        for name in ["app.jar", "bundle.tar"] {
+            let archive = testdata(name);
+            let got = read_member(&archive, Path::new("META-INF/application.yml")).unwrap();
+            assert_eq!(got, want, "archive={:?}", name);
+
+            let err = read_member(&archive, Path::new("META-INF/unknown.yml")).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound, "archive={:?}", name);
+        }
+    }
+}
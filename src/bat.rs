@@ -1,4 +1,5 @@
 use crate::chunk::File;
+use crate::decompress;
 use crate::printer::{Printer, PrinterOptions, TermColorSupport, TextWrapMode};
 use anyhow::{Error, Result};
 use bat::assets::HighlightingAssets;
@@ -59,33 +60,29 @@ pub struct BatPrinter<'main> {
 
 impl<'main> BatPrinter<'main> {
     pub fn new(opts: PrinterOptions<'main>) -> Self {
-        let styles = if opts.grid {
-            &[
-                StyleComponent::LineNumbers,
-                StyleComponent::Snip,
-                StyleComponent::HeaderFilename,
-                StyleComponent::Grid,
-            ][..]
-        } else {
-            &[
-                StyleComponent::LineNumbers,
-                StyleComponent::Snip,
-                StyleComponent::HeaderFilename,
-            ][..]
-        };
+        let mut styles = vec![
+            StyleComponent::LineNumbers,
+            StyleComponent::Snip,
+            StyleComponent::HeaderFilename,
+        ];
+        if opts.grid {
+            styles.push(StyleComponent::Grid);
+        }
 
         let wrapping_mode = match opts.text_wrap {
-            TextWrapMode::Char => WrappingMode::Character,
-            TextWrapMode::Never => WrappingMode::NoWrapping(true),
+            TextWrapMode::Char | TextWrapMode::Word => WrappingMode::Character, // bat does not support word-wise text-wrapping
+            TextWrapMode::Truncate | TextWrapMode::Never => WrappingMode::NoWrapping(true), // bat does not support truncating lines
         };
 
         let mut config = Config {
             colored_output: true,
             term_width: opts.term_width as usize,
-            style_components: StyleComponents::new(styles),
+            style_components: StyleComponents::new(&styles),
             tab_width: opts.tab_width,
             true_color: opts.color_support == TermColorSupport::True,
             wrapping_mode,
+            show_nonprintable: opts.show_all,
+            language: opts.language,
             ..Default::default()
         };
 
@@ -162,8 +159,12 @@ impl<'main> BatPrinter<'main> {
         };
         config.visible_lines = VisibleLines::Ranges(LineRanges::from(ranges));
 
-        let input =
-            Input::from_reader(Box::new(file.contents.as_ref())).with_name(Some(&file.path));
+        // Syntax of a compressed file is detected from the name of the file inside it
+        let mut input = Input::from_reader(Box::new(file.contents.as_ref()))
+            .with_name(Some(decompress::inner_path(&file.path)));
+        input
+            .description_mut()
+            .set_title(Some(file.path.to_string_lossy().into_owned()));
 
         let ranges = file
             .line_matches
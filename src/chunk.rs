@@ -1,26 +1,35 @@
-use crate::grep::GrepMatch;
-use anyhow::Result;
+use crate::archive;
+use crate::decompress;
+use crate::grep::{GrepMatch, Replacement};
+use crate::pre::PreCommand;
+use anyhow::{Error, Result};
+use encoding_rs_io::DecodeReaderBytesBuilder;
 use memchr::{memchr_iter, Memchr};
 use pathdiff::diff_paths;
 use std::cmp;
 use std::env;
-use std::fs;
+use std::io::{self, Read};
 use std::iter::Peekable;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 #[derive(Clone)] // Implement Clone for benchmark
 pub struct LineMatch {
     pub line_number: u64,
     // Byte offsets of start/end positions within the line. Inherit from GrepMatch
-    pub ranges: Vec<(usize, usize)>,
+    pub ranges: Vec<(usize, usize, usize)>,
+    // Lines replacing this line on previewing `--replace`. Inherit from GrepMatch
+    pub replaced: Option<Vec<Replacement>>,
 }
 
 impl LineMatch {
-    pub fn new(line_number: u64, ranges: Vec<(usize, usize)>) -> Self {
+    pub fn new(line_number: u64, ranges: Vec<(usize, usize, usize)>) -> Self {
         Self {
             line_number,
             ranges,
+            replaced: None,
         }
     }
 
@@ -28,6 +37,17 @@ impl LineMatch {
         Self {
             line_number,
             ranges: vec![],
+            replaced: None,
+        }
+    }
+}
+
+impl From<GrepMatch> for LineMatch {
+    fn from(m: GrepMatch) -> Self {
+        Self {
+            line_number: m.line_number,
+            ranges: m.ranges,
+            replaced: m.replaced,
         }
     }
 }
@@ -38,7 +58,8 @@ pub struct File {
     pub path: PathBuf,
     pub line_matches: Box<[LineMatch]>,
     pub chunks: Box<[(u64, u64)]>,
-    pub contents: Box<[u8]>,
+    // Shared so that previews of chunks and replacements can refer to the contents without copying them
+    pub contents: Arc<[u8]>,
 }
 
 impl File {
@@ -52,14 +73,14 @@ impl File {
             path,
             line_matches: lm.into_boxed_slice(),
             chunks: chunks.into_boxed_slice(),
-            contents: contents.into_boxed_slice(),
+            contents: contents.into(),
         }
     }
 
     pub fn sample_file() -> Self {
         let lmats = vec![
-            LineMatch::new(3, vec![(4, 7)]),
-            LineMatch::new(4, vec![(7, 10)]),
+            LineMatch::new(3, vec![(4, 7, 0)]),
+            LineMatch::new(4, vec![(7, 10, 0)]),
         ];
         let chunks = vec![(1, 7)];
         let contents = b"\
@@ -75,12 +96,79 @@ fn print_sqrt<S: AsRef<str>>(input: S) {
     }
 }
 
+// Text encoding of searched files. Contents are transcoded into UTF-8 before searching and printing
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    // Detect UTF-8 and UTF-16 by BOM. Otherwise treat contents as UTF-8
+    #[default]
+    Auto,
+    // Treat contents as UTF-8 without sniffing BOM
+    None,
+    // Transcode contents from the encoding unless BOM is found
+    Label(&'static encoding_rs::Encoding),
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        if input.eq_ignore_ascii_case("auto") {
+            return Ok(Encoding::Auto);
+        }
+        if input.eq_ignore_ascii_case("none") {
+            return Ok(Encoding::None);
+        }
+        match encoding_rs::Encoding::for_label_no_replacement(input.as_bytes()) {
+            Some(enc) => Ok(Encoding::Label(enc)),
+            None => anyhow::bail!(
+                "Unknown encoding {:?}. See https://encoding.spec.whatwg.org/#concept-encoding-get for the list of labels",
+                input,
+            ),
+        }
+    }
+}
+
+impl Encoding {
+    // Transcode the contents into UTF-8 in the same way as grep-searcher does so that byte offsets of
+    // matches reported by the searcher point the same positions in the transcoded contents
+    pub fn decode(self, contents: Vec<u8>) -> io::Result<Vec<u8>> {
+        let (label, sniff) = match self {
+            Encoding::Auto => (None, true),
+            Encoding::None => (None, false),
+            Encoding::Label(enc) => (Some(enc), true),
+        };
+
+        let has_bom = contents.starts_with(b"\xef\xbb\xbf")
+            || contents.starts_with(b"\xff\xfe")
+            || contents.starts_with(b"\xfe\xff");
+        if label.is_none() && !(sniff && has_bom) {
+            return Ok(contents); // Fast path: No need to transcode
+        }
+
+        let mut decoder = DecodeReaderBytesBuilder::new()
+            .encoding(label)
+            .utf8_passthru(true)
+            .strip_bom(sniff)
+            .bom_override(true)
+            .bom_sniffing(sniff)
+            .build(contents.as_slice());
+        let mut decoded = Vec::with_capacity(contents.len());
+        decoder.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
 pub struct Files<I: Iterator> {
     iter: Peekable<I>,
     min_context: u64,
     max_context: u64,
     saw_error: bool,
     cwd: Option<PathBuf>,
+    encoding: Encoding,
+    decompress: bool,
+    pre: Option<PreCommand>,
+    archive: bool,
+    loaded: Vec<(PathBuf, Vec<u8>)>,
 }
 
 impl<I: Iterator> Files<I> {
@@ -91,8 +179,43 @@ impl<I: Iterator> Files<I> {
             max_context,
             saw_error: false,
             cwd: env::current_dir().ok(),
+            encoding: Encoding::Auto,
+            decompress: false,
+            pre: None,
+            archive: false,
+            loaded: vec![],
         }
     }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    // Decompress contents of compressed files such as `.gz` before splitting them into chunks
+    pub fn decompress(mut self, yes: bool) -> Self {
+        self.decompress = yes;
+        self
+    }
+
+    // Run the preprocessor command for the files matching its globs and use its output as their contents
+    pub fn pre(mut self, pre: Option<PreCommand>) -> Self {
+        self.pre = pre;
+        self
+    }
+
+    // Read contents of archive members from their archives. Paths of members are separated from archive paths with
+    // `!/` like `libs/app.jar!/META-INF/application.yml`
+    pub fn archive(mut self, yes: bool) -> Self {
+        self.archive = yes;
+        self
+    }
+
+    // Contents of the file which were already read such as stdin. They are used instead of reading the file at the path
+    pub fn contents(mut self, path: PathBuf, contents: Vec<u8>) -> Self {
+        self.loaded.push((path, contents));
+        self
+    }
 }
 
 pub struct Line<'a>(pub &'a [u8], pub u64);
@@ -208,6 +331,7 @@ impl<I: Iterator<Item = Result<GrepMatch>>> Iterator for Files<I> {
             path,
             mut line_number,
             ranges,
+            replaced,
         } = match self.iter.next()? {
             Ok(m) => m,
             Err(e) => {
@@ -215,7 +339,17 @@ impl<I: Iterator<Item = Result<GrepMatch>>> Iterator for Files<I> {
                 return Some(Err(e));
             }
         };
-        let contents = match fs::read(&path) {
+        let contents = if let Some(idx) = self.loaded.iter().position(|(p, _)| *p == path) {
+            Ok(self.loaded.swap_remove(idx).1)
+        } else {
+            let member = archive::split_path(&path).filter(|_| self.archive);
+            match (member, &self.pre) {
+                (Some((archive, member)), _) => archive::read_member(archive, member),
+                (None, Some(pre)) if pre.matches(&path) => pre.read(&path),
+                (None, _) => decompress::read(&path, self.decompress),
+            }
+        };
+        let contents = match contents.and_then(|vec| self.encoding.decode(vec)) {
             Ok(vec) => vec,
             Err(err) => {
                 self.saw_error = true;
@@ -227,6 +361,7 @@ impl<I: Iterator<Item = Result<GrepMatch>>> Iterator for Files<I> {
         let mut lmats = vec![LineMatch {
             line_number,
             ranges,
+            replaced,
         }];
         let mut chunks = Vec::new();
 
@@ -261,7 +396,7 @@ impl<I: Iterator<Item = Result<GrepMatch>>> Iterator for Files<I> {
                         // Next match
                         let m = self.iter.next().unwrap().unwrap();
                         line_number = m.line_number;
-                        lmats.push(LineMatch::new(line_number, m.ranges));
+                        lmats.push(LineMatch::from(m));
                     }
                 }
 
@@ -277,7 +412,7 @@ impl<I: Iterator<Item = Result<GrepMatch>>> Iterator for Files<I> {
             let m = self.iter.next().unwrap().unwrap();
             line_number = m.line_number;
             // First match line of next chunk
-            lmats.push(LineMatch::new(line_number, m.ranges));
+            lmats.push(LineMatch::from(m));
         }
 
         if chunks.is_empty() {
@@ -296,6 +431,7 @@ mod tests {
     use crate::test;
     use anyhow::Error;
     use std::fmt;
+    use std::fs;
     use std::path::Path;
 
     fn test_success_case(inputs: &[&str]) {
@@ -379,7 +515,7 @@ mod tests {
         let expected = File {
             line_matches: vec![LineMatch::lnum(8)].into_boxed_slice(),
             chunks: vec![(5, 11)].into_boxed_slice(),
-            contents: fs::read(&path).unwrap().into_boxed_slice(),
+            contents: fs::read(&path).unwrap().into(),
             path,
         };
 
@@ -399,7 +535,7 @@ mod tests {
         let expected = File {
             line_matches: vec![LineMatch::lnum(8)].into_boxed_slice(),
             chunks: vec![(8, 8)].into_boxed_slice(),
-            contents: fs::read(&path).unwrap().into_boxed_slice(),
+            contents: fs::read(&path).unwrap().into(),
             path,
         };
 
@@ -407,6 +543,31 @@ mod tests {
         assert_eq!(got[0], expected);
     }
 
+    #[test]
+    fn test_loaded_contents() {
+        let path = PathBuf::from("<stdin>");
+        let contents = b"foo\nbar\nbaz\n".to_vec();
+        let matches = vec![Ok(GrepMatch {
+            path: path.clone(),
+            line_number: 2,
+            ranges: vec![],
+            replaced: None,
+        })];
+        let got: Vec<_> = Files::new(matches.into_iter(), 0, 0)
+            .contents(path.clone(), contents.clone())
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let expected = File {
+            line_matches: vec![LineMatch::lnum(2)].into_boxed_slice(),
+            chunks: vec![(2, 2)].into_boxed_slice(),
+            contents: contents.into(),
+            path,
+        };
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0], expected);
+    }
+
     #[test]
     fn test_error_while_matching() {
         #[derive(Debug)]
@@ -424,4 +585,30 @@ mod tests {
             .unwrap_err();
         assert_eq!(format!("{}", err), "dummy error!");
     }
+
+    #[test]
+    fn test_decode_encoding() {
+        let tests: &[(&str, &[u8], &str)] = &[
+            ("auto", b"abc \xe3\x81\x82", "abc \u{3042}"),
+            ("auto", b"\xef\xbb\xbfabc", "abc"),
+            ("auto", b"\xff\xfea\x00b\x00", "ab"),
+            ("auto", b"\xfe\xff\x00a\x00b", "ab"),
+            ("none", b"\xef\xbb\xbfabc", "\u{feff}abc"),
+            ("latin1", b"caf\xe9", "caf\u{e9}"),
+            ("Shift_JIS", b"\x82\xa0", "\u{3042}"),
+            ("shift_jis", b"\xff\xfea\x00", "a"), // BOM overrides the encoding
+        ];
+        for (label, input, want) in tests {
+            let enc: Encoding = label.parse().unwrap();
+            let got = enc.decode(input.to_vec()).unwrap();
+            assert_eq!(
+                String::from_utf8(got).unwrap(),
+                *want,
+                "label={:?} input={:?}",
+                label,
+                input
+            );
+        }
+        "unknown-encoding".parse::<Encoding>().unwrap_err();
+    }
 }
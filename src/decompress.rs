@@ -0,0 +1,91 @@
+use grep_cli::DecompressionReader;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+// Extensions of compressed files which are decompressed in the same way as ripgrep's `--search-zip`. Archives such
+// as `.tgz` are not included since they bundle multiple files
+const EXTENSIONS: &[&str] = &["gz", "bz2", "xz", "lz4", "lzma", "br", "zst", "zstd", "Z"];
+
+pub fn is_compressed(path: &Path) -> bool {
+    matches!(path.extension().and_then(OsStr::to_str), Some(ext) if EXTENSIONS.contains(&ext))
+}
+
+// Path of the file compressed in the given file. For example, `access.log.gz` contains `access.log`. The path is
+// returned as-is when it is not a compressed file
+pub fn inner_path(path: &Path) -> Cow<'_, Path> {
+    if is_compressed(path) {
+        Cow::Owned(path.with_extension(""))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+// Open the compressed file. Its contents are decompressed by an external command such as `gzip` or `xz` as ripgrep
+// does. When the command is not found, the contents are read as-is
+pub fn open(path: &Path) -> io::Result<DecompressionReader> {
+    Ok(DecompressionReader::new(path)?)
+}
+
+// Read the contents of the file. When `decompress` is true and the file is compressed, the decompressed contents
+// are returned
+pub fn read(path: &Path, decompress: bool) -> io::Result<Vec<u8>> {
+    if !decompress || !is_compressed(path) {
+        return fs::read(path);
+    }
+    let mut reader = open(path)?;
+    let mut contents = vec![];
+    reader.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_inner_path() {
+        let tests = [
+            ("access.log.gz", "access.log"),
+            ("dir/data.json.zst", "dir/data.json"),
+            ("main.rs.bz2", "main.rs"),
+            ("foo.xz", "foo"),
+            ("main.rs", "main.rs"),
+            ("archive.tgz", "archive.tgz"),
+            ("Makefile", "Makefile"),
+        ];
+        for (path, want) in tests.iter() {
+            let got = inner_path(Path::new(path));
+            assert_eq!(got, PathBuf::from(want), "path={:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_read_compressed() {
+        let dir = Path::new("testdata").join("decompress");
+        let want = fs::read(dir.join("access.log")).unwrap();
+        for name in [
+            "access.log.gz",
+            "access.log.bz2",
+            "access.log.xz",
+            "access.log.zst",
+        ] {
+            let path = dir.join(name);
+            if grep_cli::DecompressionMatcher::new()
+                .command(&path)
+                .is_none()
+            {
+                continue; // The decompression command is not installed
+            }
+            let got = read(&path, true).unwrap();
+            assert_eq!(got, want, "file={:?}", name);
+        }
+
+        // Contents are not decompressed when not requested
+        let path = dir.join("access.log.gz");
+        assert_eq!(read(&path, false).unwrap(), fs::read(&path).unwrap());
+    }
+}
@@ -0,0 +1,341 @@
+#[cfg(feature = "git")]
+use git2::{Diff, DiffOptions, IntoCString, Repository};
+use std::collections::HashMap;
+#[cfg(feature = "git")]
+use std::fs;
+#[cfg(feature = "git")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "git")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineChange {
+    Added,
+    Modified,
+    RemovedAbove,
+    RemovedBelow,
+}
+
+pub type LineChanges = HashMap<u64, LineChange>;
+
+// Blame information of a line. Values are formatted for display
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Blame {
+    pub commit: String, // Short commit hash
+    pub author: String,
+    pub date: String, // Relative date such as "3 days ago"
+}
+
+impl Blame {
+    pub fn uncommitted() -> Self {
+        Self {
+            commit: "0000000".to_string(),
+            author: "Uncommitted".to_string(),
+            date: String::new(),
+        }
+    }
+}
+
+pub type LineBlames = HashMap<u64, Blame>;
+
+// Returns the repository containing the file and the path of the file relative to the root of the repository
+#[cfg(feature = "git")]
+fn open_repository(path: &Path) -> Option<(Repository, PathBuf)> {
+    let repo = Repository::discover(path).ok()?;
+    let root = fs::canonicalize(repo.workdir()?).ok()?;
+    let path = fs::canonicalize(path).ok()?;
+    let path = path.strip_prefix(&root).ok()?.to_path_buf();
+    Some((repo, path))
+}
+
+#[cfg(feature = "git")]
+fn diff_options(path: &Path) -> Option<DiffOptions> {
+    let mut opts = DiffOptions::new();
+    opts.pathspec(path.into_c_string().ok()?);
+    opts.context_lines(0);
+    Some(opts)
+}
+
+// Visit (old_start, old_lines, new_start, new_lines) of each hunk in the diff of the file
+#[cfg(feature = "git")]
+fn foreach_hunk(diff: &Diff<'_>, path: &Path, mut f: impl FnMut(u32, u32, u32, u32)) -> Option<()> {
+    diff.foreach(
+        &mut |_, _| true,
+        None,
+        Some(&mut |delta, hunk| {
+            if delta.new_file().path() != Some(path) {
+                return false;
+            }
+            f(
+                hunk.old_start(),
+                hunk.old_lines(),
+                hunk.new_start(),
+                hunk.new_lines(),
+            );
+            true
+        }),
+        None,
+    )
+    .ok()
+}
+
+// Compute changes of lines in the file compared with the index of the Git repository. The changes are
+// the same as bat's `changes` style component. `None` is returned when the file is not in a Git repository
+#[cfg(feature = "git")]
+pub fn line_changes(path: &Path) -> Option<LineChanges> {
+    let (repo, path) = open_repository(path)?;
+    let mut opts = diff_options(&path)?;
+    let diff = repo.diff_index_to_workdir(None, Some(&mut opts)).ok()?;
+
+    let mut changes = LineChanges::new();
+    let mut mark = |start: u32, end: u32, change: LineChange| {
+        for lnum in start..=end {
+            changes.insert(lnum as u64, change);
+        }
+    };
+
+    foreach_hunk(&diff, &path, |_, old_lines, new_start, new_lines| {
+        if old_lines == 0 && new_lines > 0 {
+            mark(new_start, new_start + new_lines - 1, LineChange::Added);
+        } else if new_lines == 0 && old_lines > 0 {
+            if new_start == 0 {
+                mark(1, 1, LineChange::RemovedAbove);
+            } else {
+                mark(new_start, new_start, LineChange::RemovedBelow);
+            }
+        } else {
+            mark(new_start, new_start + new_lines - 1, LineChange::Modified);
+        }
+    })?;
+
+    Some(changes)
+}
+
+// Format elapsed seconds like "3 days ago"
+#[cfg(feature = "git")]
+fn relative_date(elapsed: i64) -> String {
+    const UNITS: [(&str, i64); 6] = [
+        ("year", 365 * 24 * 60 * 60),
+        ("month", 30 * 24 * 60 * 60),
+        ("week", 7 * 24 * 60 * 60),
+        ("day", 24 * 60 * 60),
+        ("hour", 60 * 60),
+        ("minute", 60),
+    ];
+    for (unit, secs) in UNITS {
+        let n = elapsed / secs;
+        if n > 0 {
+            let plural = if n == 1 { "" } else { "s" };
+            return format!("{} {}{} ago", n, unit, plural);
+        }
+    }
+    "just now".to_string()
+}
+
+// Line number in the committed file corresponding to the line number in the working tree. `None` means
+// the line is not committed yet. `hunks` are (old_start, old_lines, new_start, new_lines) sorted by position
+#[cfg(feature = "git")]
+fn committed_line(lnum: u32, hunks: &[(u32, u32, u32, u32)]) -> Option<u32> {
+    let mut committed = lnum;
+    for &(old_start, old_lines, new_start, new_lines) in hunks {
+        if new_start <= lnum && lnum < new_start + new_lines {
+            return None;
+        }
+        // Zero-length range in a hunk header points the line before the range
+        let new_end = if new_lines == 0 {
+            new_start + 1
+        } else {
+            new_start + new_lines
+        };
+        let old_end = if old_lines == 0 {
+            old_start + 1
+        } else {
+            old_start + old_lines
+        };
+        if lnum < new_end {
+            break;
+        }
+        committed = lnum - new_end + old_end;
+    }
+    Some(committed)
+}
+
+// Compute blame information of the given lines in the file. Blame is computed for the contents at HEAD and line
+// numbers in the working tree are mapped to the ones at HEAD through the diff between them. `None` is returned
+// when the file is not committed in a Git repository
+#[cfg(feature = "git")]
+pub fn line_blames(path: &Path, lnums: impl Iterator<Item = u64>) -> Option<LineBlames> {
+    let (repo, path) = open_repository(path)?;
+    let blame = repo.blame_file(&path, None).ok()?;
+
+    let head = repo.head().ok()?.peel_to_tree().ok()?;
+    let mut opts = diff_options(&path)?;
+    let diff = repo
+        .diff_tree_to_workdir_with_index(Some(&head), Some(&mut opts))
+        .ok()?;
+    let mut hunks = vec![];
+    foreach_hunk(
+        &diff,
+        &path,
+        |old_start, old_lines, new_start, new_lines| {
+            hunks.push((old_start, old_lines, new_start, new_lines));
+        },
+    )?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let mut blames = LineBlames::new();
+    for lnum in lnums {
+        let committed = match committed_line(lnum as u32, &hunks) {
+            Some(n) => n,
+            None => {
+                blames.insert(lnum, Blame::uncommitted());
+                continue;
+            }
+        };
+        if let Some(hunk) = blame.get_line(committed as usize) {
+            let sig = hunk.final_signature();
+            let id = hunk.final_commit_id().to_string();
+            blames.insert(
+                lnum,
+                Blame {
+                    commit: id[..7].to_string(),
+                    author: sig.name().unwrap_or("").to_string(),
+                    date: relative_date(now - sig.when().seconds()),
+                },
+            );
+        }
+    }
+
+    Some(blames)
+}
+
+#[cfg(all(test, feature = "git"))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_line_changes() {
+        let dir = env::temp_dir().join(format!("hgrep-git-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+
+        let tests = [
+            ("unchanged.txt", "a\nb\nc\n", vec![]),
+            (
+                "added_and_modified.txt",
+                "a\nB\nc\nd\n",
+                vec![(2, LineChange::Modified), (4, LineChange::Added)],
+            ),
+            (
+                "removed_first.txt",
+                "b\nc\n",
+                vec![(1, LineChange::RemovedAbove)],
+            ),
+            (
+                "removed_middle.txt",
+                "a\nc\n",
+                vec![(1, LineChange::RemovedBelow)],
+            ),
+        ];
+
+        let mut index = repo.index().unwrap();
+        for (name, _, _) in tests.iter() {
+            fs::write(dir.join(name), "a\nb\nc\n").unwrap();
+            index.add_path(Path::new(name)).unwrap();
+        }
+        index.write().unwrap();
+
+        for (name, contents, want) in tests {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            let got = line_changes(&path).unwrap();
+            let want: LineChanges = want.into_iter().collect();
+            assert_eq!(got, want, "file={:?}", name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_relative_date() {
+        let tests = [
+            (0, "just now"),
+            (59, "just now"),
+            (60, "1 minute ago"),
+            (59 * 60, "59 minutes ago"),
+            (2 * 60 * 60, "2 hours ago"),
+            (24 * 60 * 60, "1 day ago"),
+            (13 * 24 * 60 * 60, "1 week ago"),
+            (45 * 24 * 60 * 60, "1 month ago"),
+            (3 * 365 * 24 * 60 * 60, "3 years ago"),
+        ];
+        for (elapsed, want) in tests.iter() {
+            assert_eq!(relative_date(*elapsed), *want, "elapsed={}", elapsed);
+        }
+    }
+
+    #[test]
+    fn test_committed_line() {
+        // Line 3 was inserted after line 2, old line 5 was removed and old line 8 was modified
+        let hunks = [(2, 0, 3, 1), (5, 1, 5, 0), (8, 1, 8, 1)];
+        let tests = [
+            (1, Some(1)),
+            (2, Some(2)),
+            (3, None),
+            (4, Some(3)),
+            (5, Some(4)),
+            (6, Some(6)),
+            (7, Some(7)),
+            (8, None),
+            (9, Some(9)),
+        ];
+        for (lnum, want) in tests.iter() {
+            assert_eq!(committed_line(*lnum, &hunks), *want, "lnum={}", lnum);
+        }
+    }
+
+    #[test]
+    fn test_line_blames() {
+        let dir = env::temp_dir().join(format!("hgrep-git-blame-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+
+        let path = dir.join("test.txt");
+        fs::write(&path, "a\nb\nc\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig =
+            git2::Signature::new("Alice", "alice@example.com", &git2::Time::new(0, 0)).unwrap();
+        let id = repo
+            .commit(Some("HEAD"), &sig, &sig, "first commit", &tree, &[])
+            .unwrap();
+        let commit = id.to_string()[..7].to_string();
+
+        // Insert a new line at the top of the file in working tree
+        fs::write(&path, "x\na\nb\nc\n").unwrap();
+
+        let blames = line_blames(&path, 1..=4).unwrap();
+        assert_eq!(blames[&1], Blame::uncommitted());
+        for lnum in 2..=4 {
+            let blame = &blames[&lnum];
+            assert_eq!(blame.commit, commit, "lnum={}", lnum);
+            assert_eq!(blame.author, "Alice", "lnum={}", lnum);
+            assert!(
+                blame.date.ends_with("years ago"),
+                "lnum={} date={:?}",
+                lnum,
+                blame.date
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -51,12 +51,24 @@ impl fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+// Line replacing a matched line on previewing `--replace`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replacement {
+    pub text: Vec<u8>, // Replaced line without line terminator
+    // Byte offsets of start/end positions of replaced texts within the line and indices of their patterns
+    pub ranges: Vec<(usize, usize, usize)>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct GrepMatch {
     pub path: PathBuf,
     pub line_number: u64,
-    // Byte offsets of start/end positions within the line
-    pub ranges: Vec<(usize, usize)>,
+    // Byte offsets of start/end positions within the line and indices of the matched patterns. The index is
+    // used for highlighting regions matched to multiple patterns in different colors
+    pub ranges: Vec<(usize, usize, usize)>,
+    // Lines replacing this line on previewing `--replace`. When a match spans multiple lines, all the replaced
+    // lines are put to the last line of the match and the other lines have an empty vector
+    pub replaced: Option<Vec<Replacement>>,
 }
 
 pub struct GrepLines<R: BufRead> {
@@ -84,6 +96,7 @@ fn parse_line(line: Vec<u8>) -> Result<GrepMatch> {
             path: PathBuf::from(bytes_to_os_string(path)),
             line_number: lnum,
             ranges: vec![], // Regions are not supported
+            replaced: None,
         }),
         None => ParseError::err(line, "Could not parse line number as unsigned integer"),
     }
@@ -129,16 +142,19 @@ fn test_read_ok() {
             path: PathBuf::from("/path/to/foo.txt"),
             line_number: 1,
             ranges: vec![],
+            replaced: None,
         },
         GrepMatch {
             path: PathBuf::from("/path/to/bar.txt"),
             line_number: 100,
             ranges: vec![],
+            replaced: None,
         },
         GrepMatch {
             path: PathBuf::from("/path/to/bar.txt"),
             line_number: 110,
             ranges: vec![],
+            replaced: None,
         },
     ];
 
@@ -31,7 +31,7 @@ mod tests {
 
     #[test]
     fn test_do_not_ignore_other_errors() {
-        let err = Error::new(ErrorKind::Other, "oops");
+        let err = Error::other("oops");
         let res = Result::<i32>::Err(err);
         let res = res.ignore_broken_pipe();
         res.unwrap_err();
@@ -1,16 +1,25 @@
 #[cfg(not(any(feature = "bat-printer", feature = "syntect-printer")))]
 compile_error!("Either feature \"bat-printer\" or \"syntect-printer\" must be enabled");
 
+pub mod archive;
 pub mod chunk;
+pub mod decompress;
+pub mod git;
 pub mod grep;
+pub mod pre;
 pub mod printer;
+pub mod sort;
 
 mod io;
 
 #[cfg(feature = "bat-printer")]
 pub mod bat;
 #[cfg(feature = "ripgrep")]
+pub mod replace;
+#[cfg(feature = "ripgrep")]
 pub mod ripgrep;
+#[cfg(feature = "server")]
+pub mod server;
 #[cfg(feature = "syntect-printer")]
 pub mod syntect;
 
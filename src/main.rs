@@ -1,7 +1,13 @@
 use anyhow::{Context, Result};
 use clap::{Arg, Command};
-use hgrep::grep::BufReadExt;
+use hgrep::chunk::{Encoding, Files};
+use hgrep::grep::{BufReadExt, GrepMatch};
+#[cfg(feature = "git")]
+use hgrep::printer::BlameLines;
 use hgrep::printer::{PrinterOptions, TextWrapMode};
+#[cfg(feature = "syntect-printer")]
+use hgrep::printer::{RegionStyle, TermColor};
+use hgrep::sort::{Sort, SortKey};
 use std::cmp;
 use std::env;
 use std::io;
@@ -69,7 +75,7 @@ fn command() -> Command {
                 .num_args(1)
                 .value_name("NUM")
                 .default_value("4")
-                .help("Number of spaces for tab character. Set 0 to pass tabs through directly"),
+                .help("Width of tab stops. Tab character is expanded to spaces until the next tab stop. Set 0 to pass tabs through directly"),
         )
         .arg(
             Arg::new("theme")
@@ -78,6 +84,13 @@ fn command() -> Command {
                 .value_name("THEME")
                 .help("Theme for syntax highlighting. Use --list-themes flag to print the theme list"),
         )
+        .arg(
+            Arg::new("language")
+                .long("language")
+                .num_args(1)
+                .value_name("LANG")
+                .help("Syntax name or file extension such as 'YAML' or 'yml' to highlight code snippets with. By default, syntax is detected from file paths and the first lines of contents"),
+        )
         .arg(
             Arg::new("list-themes")
                 .long("list-themes")
@@ -103,14 +116,72 @@ fn command() -> Command {
                 .num_args(1)
                 .value_name("MODE")
                 .default_value("char")
-                .value_parser(["char", "never"])
+                .value_parser(["char", "word", "truncate", "never"])
                 .ignore_case(true)
-                .help("Text-wrapping mode. 'char' enables character-wise text-wrapping. 'never' disables text-wrapping")
+                .help("Text-wrapping mode. 'char' enables character-wise text-wrapping. 'word' enables word-wise text-wrapping (only for syntect printer). 'truncate' truncates long lines around the match (only for syntect printer). 'never' disables text-wrapping")
+        ).arg(
+            Arg::new("wrap-indent")
+                .long("wrap-indent")
+                .action(clap::ArgAction::SetTrue)
+                .help("Indent wrapped lines as deep as the indentation of the original line (only for syntect printer)")
+        ).arg(
+            Arg::new("max-columns")
+                .long("max-columns")
+                .num_args(1)
+                .value_name("NUM")
+                .help("Truncate lines longer than NUM columns around the match. This implies '--wrap truncate' (only for syntect printer)")
+        ).arg(
+            Arg::new("approx-highlight")
+                .long("approx-highlight")
+                .num_args(1)
+                .value_name("NUM")
+                .help("Start parsing syntax at most NUM lines before each snippet instead of the beginning of the file. This makes highlighting matches deep in large files faster, but highlighting may be incorrect when NUM lines are not enough to know the context such as a long block comment (only for syntect printer)")
+        ).arg(
+            Arg::new("show-all")
+                .long("show-all")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show invisible characters such as tabs, trailing whitespaces, carriage returns and control characters with visible symbols")
+        ).arg(
+            Arg::new("encoding")
+                .short('E')
+                .long("encoding")
+                .num_args(1)
+                .value_name("ENCODING")
+                .default_value("auto")
+                .help("Text encoding of files such as 'utf-16le', 'shift_jis' or 'latin1'. Contents are transcoded into UTF-8 before searching and printing. 'auto' detects UTF-8 and UTF-16 by BOM. 'none' disables the detection")
+        ).arg(
+            Arg::new("search-zip")
+                .short('z')
+                .long("search-zip")
+                .action(clap::ArgAction::SetTrue)
+                .help("Search in compressed files (gzip, bzip2, xz, LZ4, LZMA, Brotli, Zstd and compress). They are decompressed by external commands such as 'gzip' or 'xz' like ripgrep does. Code snippets are highlighted with the syntax of the file inside")
+        ).arg(
+            Arg::new("sort")
+                .long("sort")
+                .num_args(1)
+                .value_name("SORTBY")
+                .value_parser(["path", "modified", "created", "none"])
+                .conflicts_with("sortr")
+                .help("Print files in ascending order by 'path', 'modified' (last modified time) or 'created' (creation time). Files are still searched and highlighted in parallel and printed as soon as their preceding files are printed. 'none' prints files in arbitrary order for the best performance (default)")
+        ).arg(
+            Arg::new("sortr")
+                .long("sortr")
+                .num_args(1)
+                .value_name("SORTBY")
+                .value_parser(["path", "modified", "created", "none"])
+                .help("Print files in descending order. See --sort for the sort keys")
+        ).arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .num_args(1)
+                .value_name("NUM")
+                .help("The approximate number of threads to search and print files. 0 chooses it automatically based on the number of CPUs (default)")
         ).arg(
             Arg::new("first-only")
                 .short('f')
                 .long("first-only")
-                .help("Show only the first code snippet per file")
+                .help("Show only the first code snippet per file. Note that -f is not the short form of --file unlike grep and ripgrep")
         )
         .arg(
             Arg::new("generate-completion-script")
@@ -122,6 +193,34 @@ fn command() -> Command {
                 .help("Print completion script for SHELL to stdout"),
         );
 
+    #[cfg(feature = "git")]
+    let cmd = cmd
+        .arg(
+            Arg::new("git-changes")
+                .long("git-changes")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show markers of added, modified and removed lines compared with the Git index in gutter. This flag is only for syntect printer"),
+        )
+        .arg(
+            Arg::new("blame")
+                .long("blame")
+                .num_args(0..=1)
+                .require_equals(true)
+                .value_name("LINES")
+                .default_missing_value("matched")
+                .value_parser(["matched", "all"])
+                .help("Annotate lines with short commit hash, author and relative date by `git blame`. 'matched' annotates only matched lines and 'all' annotates all lines in snippets. Annotations are omitted when the terminal is too narrow for them. This option is only for syntect printer"),
+        );
+
+    #[cfg(feature = "server")]
+    let cmd = cmd.arg(
+        Arg::new("server")
+            .long("server")
+            .action(clap::ArgAction::SetTrue)
+            .conflicts_with_all(["PATTERN", "regexp", "file"])
+            .help("Serve search requests in JSON-RPC 2.0 messages delimited by newlines over stdin and stdout. Assets for syntax highlighting are loaded only once. This flag is only for syntect printer"),
+    );
+
     #[cfg(feature = "bat-printer")]
     let cmd = cmd.arg(
         Arg::new("custom-assets")
@@ -140,6 +239,48 @@ fn command() -> Command {
             Arg::new("ascii-lines").long("ascii-lines").help(
                 "Use ASCII characters for drawing border lines instead of Unicode characters",
             ),
+        )
+        .arg(
+            Arg::new("match-bg")
+                .long("match-bg")
+                .num_args(1)
+                .value_name("COLOR")
+                .help("Background color of matched lines. COLOR is '#rrggbb', '#rgb', ANSI color number (0..255) or color name such as 'yellow' or 'bright-red'. This option is only for syntect printer"),
+        )
+        .arg(
+            Arg::new("match-lnum-fg")
+                .long("match-lnum-fg")
+                .num_args(1)
+                .value_name("COLOR")
+                .help("Foreground color of line numbers of matched lines. See --match-bg for the format of COLOR. This option is only for syntect printer"),
+        )
+        .arg(
+            Arg::new("region-fg")
+                .long("region-fg")
+                .num_args(1)
+                .value_name("COLOR")
+                .help("Foreground color of matched regions. See --match-bg for the format of COLOR. This option is only for syntect printer"),
+        )
+        .arg(
+            Arg::new("region-bg")
+                .long("region-bg")
+                .num_args(1)
+                .value_name("COLOR")
+                .help("Background color of matched regions. See --match-bg for the format of COLOR. This option is only for syntect printer"),
+        )
+        .arg(
+            Arg::new("gutter-fg")
+                .long("gutter-fg")
+                .num_args(1)
+                .value_name("COLOR")
+                .help("Foreground color of line numbers and border lines. See --match-bg for the format of COLOR. This option is only for syntect printer"),
+        )
+        .arg(
+            Arg::new("region-style")
+                .long("region-style")
+                .num_args(1)
+                .value_name("STYLE")
+                .help("Font style of matched regions. STYLE is a comma-separated list of 'bold', 'underline', 'inverse' or 'none'. This option is only for syntect printer"),
         );
 
     #[cfg(feature = "ripgrep")]
@@ -191,6 +332,38 @@ fn command() -> Command {
                     .long("glob-case-insensitive")
                     .help("Process glob patterns given with the -g/--glob flag case insensitively"),
             )
+            .arg(
+                Arg::new("label")
+                    .long("label")
+                    .num_args(1)
+                    .value_name("NAME")
+                    .help("Name of stdin shown in the header when '-' is given as path. The name is also used to detect syntax of the contents. The default is '<stdin>'"),
+            )
+            .arg(
+                Arg::new("search-archive")
+                    .long("search-archive")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with("write")
+                    .help("Search files in archives (zip, jar, war, ear and tar optionally compressed like .tar.gz). Each file in an archive is shown with a header like 'libs/app.jar!/META-INF/application.yml' and highlighted with the syntax of the inner path. Globs and file types are matched with paths of archives"),
+            )
+            .arg(
+                Arg::new("pre")
+                    .long("pre")
+                    .num_args(1)
+                    .value_name("COMMAND")
+                    .value_hint(clap::ValueHint::CommandName)
+                    .conflicts_with("write")
+                    .help("Run the command for each file and search its output instead of the file contents like ripgrep. The file path is given as the first argument and the file contents are given via stdin. Code snippets are printed from the output. This is useful for searching converted texts of PDFs or documents"),
+            )
+            .arg(
+                Arg::new("pre-glob")
+                    .long("pre-glob")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .value_name("GLOB")
+                    .requires("pre")
+                    .help("Run the command given with --pre only for files matching the glob. This option is repeatable"),
+            )
             .arg(
                 Arg::new("fixed-strings")
                     .short('F')
@@ -238,6 +411,20 @@ fn command() -> Command {
                     .value_name("NUM")
                     .help("Limit the number of matching lines per file searched to NUM"),
             )
+            .arg(
+                Arg::new("max-total")
+                    .long("max-total")
+                    .num_args(1)
+                    .value_name("NUM")
+                    .help("Limit the total number of matching lines in all files to NUM. Searching stops and a warning is shown when more matching lines are found"),
+            )
+            .arg(
+                Arg::new("max-files")
+                    .long("max-files")
+                    .num_args(1)
+                    .value_name("NUM")
+                    .help("Limit the number of files with matches to NUM. Searching stops and a warning is shown when more files with matches are found"),
+            )
             .arg(
                 Arg::new("max-depth")
                     .long("max-depth")
@@ -317,13 +504,76 @@ fn command() -> Command {
                     .value_name("NUM+SUFFIX?")
                     .help("The upper size limit of the regex DFA. The default limit is 10M. For the size suffixes, see --max-filesize"),
             )
+            .arg(
+                Arg::new("replace")
+                    .short('r')
+                    .long("replace")
+                    .num_args(1)
+                    .value_name("TEMPLATE")
+                    .allow_hyphen_values(true)
+                    .help("Preview replacing every match with the given text like a diff. Each matched line is followed by the replaced line. Capture group indices (e.g. $1) and names (e.g. ${name}) in the text are replaced with the corresponding captured texts. This option is only available for syntect printer"),
+            )
+            .arg(
+                Arg::new("write")
+                    .long("write")
+                    .action(clap::ArgAction::SetTrue)
+                    .requires("replace")
+                    .conflicts_with("search-zip")
+                    .help("Write the replacements given by --replace to the matched files. Files are rewritten atomically and their permissions are preserved. This flag cannot be used with --search-zip, --search-archive or --pre since the searched contents are not the files themselves"),
+            )
+            .arg(
+                Arg::new("confirm")
+                    .long("confirm")
+                    .action(clap::ArgAction::SetTrue)
+                    .requires("write")
+                    .help("Ask whether to apply the replacements for each code snippet before writing them with --write. Answer y (yes), n (no), a (all the remaining) or q (quit)"),
+            )
+            .arg(
+                Arg::new("regexp")
+                    .short('e')
+                    .long("regexp")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .value_name("PATTERN")
+                    .allow_hyphen_values(true)
+                    .help("Pattern to search. This option is repeatable to search multiple patterns. When this option or --file is given, all positional arguments are treated as paths"),
+            )
+            .arg(
+                Arg::new("file")
+                    .long("file")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .value_name("PATTERNFILE")
+                    .value_hint(clap::ValueHint::FilePath)
+                    .value_parser(clap::builder::ValueParser::path_buf())
+                    .help("Search for patterns from the given file, with one pattern per line. When - is given, patterns are read from stdin. This option is repeatable. Unlike grep and ripgrep, this option has no short form -f since -f is the short form of --first-only"),
+            )
+            .arg(
+                Arg::new("and")
+                    .long("and")
+                    .num_args(1)
+                    .action(clap::ArgAction::Append)
+                    .value_name("PATTERN")
+                    .allow_hyphen_values(true)
+                    .conflicts_with("invert-match")
+                    .help("Pattern which must also be found in the file. Files which do not contain all the patterns are not shown. Regions matched to this pattern are highlighted in a different color. This option is repeatable"),
+            )
+            .arg(
+                Arg::new("near")
+                    .long("near")
+                    .num_args(1)
+                    .value_name("NUM")
+                    .requires("and")
+                    .conflicts_with("multiline")
+                    .help("Only show matched lines where the search pattern and all the patterns given with --and are found within NUM lines"),
+            )
             .arg(
                 Arg::new("PATTERN")
                     .help("Pattern to search. Regular expression is available"),
             )
             .arg(
                 Arg::new("PATH")
-                    .help("Paths to search")
+                    .help("Paths to search. '-' means searching stdin")
                     .num_args(0..)
                     .value_hint(clap::ValueHint::AnyPath)
                     .value_parser(clap::builder::ValueParser::path_buf()),
@@ -380,12 +630,56 @@ fn build_ripgrep_config(
         .line_regexp(matches.contains_id("line-regexp"))
         .invert_match(matches.contains_id("invert-match"))
         .one_file_system(matches.contains_id("one-file-system"))
-        .no_unicode(matches.contains_id("no-unicode"));
+        .no_unicode(matches.contains_id("no-unicode"))
+        .search_archive(matches.get_flag("search-archive"));
+
+    if let Some(pats) = matches.get_many::<String>("regexp") {
+        for pat in pats {
+            config.pattern(pat.as_str());
+        }
+    }
+
+    if let Some(files) = matches.get_many::<std::path::PathBuf>("file") {
+        for file in files {
+            config.pattern_file(file)?;
+        }
+    }
+
+    if let Some(pats) = matches.get_many::<String>("and") {
+        for pat in pats {
+            config.and_pattern(pat.as_str());
+        }
+    }
+
+    if let Some(num) = matches.get_one::<String>("near") {
+        let num = num
+            .parse()
+            .context("could not parse --near option value as unsigned integer")?;
+        config.near(num);
+    }
 
     if let Some(globs) = matches.get_many::<String>("glob") {
         config.globs(globs.map(String::as_str));
     }
 
+    if let Some(label) = matches.get_one::<String>("label") {
+        config.label(label);
+    }
+
+    if let Some(command) = matches.get_one::<String>("pre") {
+        config.pre(std::path::Path::new(command));
+        if let Some(globs) = matches.get_many::<String>("pre-glob") {
+            config.pre_globs(globs.map(String::as_str));
+        }
+    }
+
+    if let Some(template) = matches.get_one::<String>("replace") {
+        config
+            .replace(template)
+            .write(matches.get_flag("write"))
+            .confirm(matches.get_flag("confirm"));
+    }
+
     if let Some(num) = matches.get_one::<String>("max-count") {
         let num = num
             .parse()
@@ -393,6 +687,20 @@ fn build_ripgrep_config(
         config.max_count(num);
     }
 
+    if let Some(num) = matches.get_one::<String>("max-total") {
+        let num = num
+            .parse()
+            .context("could not parse --max-total option value as unsigned integer")?;
+        config.max_total(num);
+    }
+
+    if let Some(num) = matches.get_one::<String>("max-files") {
+        let num = num
+            .parse()
+            .context("could not parse --max-files option value as unsigned integer")?;
+        config.max_files(num);
+    }
+
     if let Some(num) = matches.get_one::<String>("max-depth") {
         let num = num
             .parse()
@@ -471,6 +779,34 @@ fn app() -> Result<bool> {
         .context("could not parse \"max-context\" option value as unsigned integer")?;
     let max_context = cmp::max(min_context, max_context);
 
+    let encoding: Encoding = matches
+        .get_one::<String>("encoding")
+        .unwrap()
+        .parse()
+        .context("could not parse --encoding option value")?;
+    let search_zip = matches.get_flag("search-zip");
+    let sort = match (
+        matches.get_one::<String>("sort"),
+        matches.get_one::<String>("sortr"),
+    ) {
+        (Some(key), _) if key != "none" => Some(Sort::new(key.parse::<SortKey>()?, false)),
+        (_, Some(key)) if key != "none" => Some(Sort::new(key.parse::<SortKey>()?, true)),
+        _ => None,
+    };
+
+    if let Some(num) = matches.get_one::<String>("threads") {
+        let num = num
+            .parse()
+            .context("could not parse --threads option value as unsigned integer")?;
+        #[cfg(any(feature = "ripgrep", feature = "syntect-printer"))]
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num)
+            .build_global()
+            .context("could not initialize thread pool")?;
+        #[cfg(not(any(feature = "ripgrep", feature = "syntect-printer")))]
+        let _: usize = num; // Files are processed sequentially without the thread pool
+    }
+
     let mut printer_opts = PrinterOptions::default();
     if let Some(width) = matches.get_one::<String>("tab") {
         printer_opts.tab_width = width
@@ -489,6 +825,9 @@ fn app() -> Result<bool> {
     if let Some(theme) = matches.get_one::<String>("theme") {
         printer_opts.theme = Some(theme);
     }
+    if let Some(lang) = matches.get_one::<String>("language") {
+        printer_opts.language = Some(lang);
+    }
 
     let is_grid = matches.contains_id("grid");
     #[cfg(feature = "bat-printer")]
@@ -520,11 +859,84 @@ fn app() -> Result<bool> {
             printer_opts.text_wrap = TextWrapMode::Never;
         } else if mode.eq_ignore_ascii_case("char") {
             printer_opts.text_wrap = TextWrapMode::Char;
+        } else if mode.eq_ignore_ascii_case("word") {
+            printer_opts.text_wrap = TextWrapMode::Word;
+            #[cfg(feature = "bat-printer")]
+            if printer_kind == PrinterKind::Bat {
+                anyhow::bail!("--wrap word is only available for syntect printer since bat does not support word-wise text-wrapping");
+            }
+        } else if mode.eq_ignore_ascii_case("truncate") {
+            printer_opts.text_wrap = TextWrapMode::Truncate;
+            #[cfg(feature = "bat-printer")]
+            if printer_kind == PrinterKind::Bat {
+                anyhow::bail!("--wrap truncate is only available for syntect printer since bat does not support truncating lines");
+            }
         } else {
             unreachable!(); // Option value was validated by clap
         }
     }
 
+    if let Some(num) = matches.get_one::<String>("max-columns") {
+        let num = num
+            .parse()
+            .context("could not parse \"max-columns\" option value as unsigned integer")?;
+        if num < 10 {
+            anyhow::bail!("Too small value at --max-columns option ({} < 10)", num);
+        }
+        printer_opts.max_columns = Some(num);
+        printer_opts.text_wrap = TextWrapMode::Truncate;
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat {
+            anyhow::bail!("--max-columns option is only available for syntect printer since bat does not support truncating lines");
+        }
+    }
+
+    if let Some(num) = matches.get_one::<String>("approx-highlight") {
+        let num = num
+            .parse()
+            .context("could not parse \"approx-highlight\" option value as unsigned integer")?;
+        printer_opts.approx_highlight = Some(num);
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat {
+            anyhow::bail!("--approx-highlight option is only available for syntect printer since bat always highlights the whole file");
+        }
+    }
+
+    if matches.get_flag("wrap-indent") {
+        printer_opts.wrap_indent = true;
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat {
+            anyhow::bail!("--wrap-indent flag is only available for syntect printer since bat does not support indenting wrapped lines");
+        }
+    }
+
+    if matches.get_flag("show-all") {
+        printer_opts.show_all = true;
+    }
+
+    #[cfg(feature = "git")]
+    if matches.get_flag("git-changes") {
+        printer_opts.git_changes = true;
+        // bat computes changes only for files opened by itself while hgrep passes the contents read by the searcher
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat {
+            anyhow::bail!("--git-changes flag is only available for syntect printer since bat cannot show changes of contents read by hgrep");
+        }
+    }
+
+    #[cfg(feature = "git")]
+    if let Some(lines) = matches.get_one::<String>("blame") {
+        printer_opts.blame = Some(match lines.as_str() {
+            "matched" => BlameLines::Matched,
+            "all" => BlameLines::All,
+            _ => unreachable!(), // Value was validated by clap
+        });
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat {
+            anyhow::bail!("--blame option is only available for syntect printer since bat does not support blame annotations");
+        }
+    }
+
     if matches.contains_id("first-only") {
         printer_opts.first_only = true;
     }
@@ -546,6 +958,37 @@ fn app() -> Result<bool> {
                 anyhow::bail!("--ascii-lines flag is only available for syntect printer since bat does not support this feature");
             }
         }
+
+        let colors = &mut printer_opts.custom_colors;
+        for (name, slot) in [
+            ("match-bg", &mut colors.match_bg),
+            ("match-lnum-fg", &mut colors.match_lnum_fg),
+            ("region-fg", &mut colors.region_fg),
+            ("region-bg", &mut colors.region_bg),
+            ("gutter-fg", &mut colors.gutter_fg),
+        ] {
+            if let Some(color) = matches.get_one::<String>(name) {
+                let color: TermColor = color
+                    .parse()
+                    .with_context(|| format!("could not parse --{} option value", name))?;
+                *slot = Some(color);
+                #[cfg(feature = "bat-printer")]
+                if printer_kind == PrinterKind::Bat {
+                    anyhow::bail!("--{} option is only available for syntect printer since bat does not support customizing colors", name);
+                }
+            }
+        }
+
+        if let Some(style) = matches.get_one::<String>("region-style") {
+            let style: RegionStyle = style
+                .parse()
+                .context("could not parse --region-style option value")?;
+            printer_opts.region_style = style;
+            #[cfg(feature = "bat-printer")]
+            if printer_kind == PrinterKind::Bat {
+                anyhow::bail!("--region-style option is only available for syntect printer since bat does not support customizing styles");
+            }
+        }
     }
 
     #[cfg(feature = "bat-printer")]
@@ -573,6 +1016,16 @@ fn app() -> Result<bool> {
         unreachable!();
     }
 
+    #[cfg(feature = "server")]
+    if matches.get_flag("server") {
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat {
+            anyhow::bail!("--server flag is only available for syntect printer");
+        }
+        hgrep::server::Server::new(printer_opts, io::stdout())?.serve(io::stdin().lock())?;
+        return Ok(true);
+    }
+
     #[cfg(feature = "ripgrep")]
     if matches.contains_id("type-list") {
         let config = build_ripgrep_config(min_context, max_context, &matches)?;
@@ -581,24 +1034,66 @@ fn app() -> Result<bool> {
     }
 
     #[cfg(feature = "ripgrep")]
-    if let Some(pattern) = matches.get_one::<String>("PATTERN") {
-        use std::path::PathBuf;
+    let patterns_given = matches.contains_id("regexp") || matches.contains_id("file");
+    #[cfg(feature = "ripgrep")]
+    if patterns_given || matches.contains_id("PATTERN") {
+        use std::path::{Path, PathBuf};
 
-        let paths = matches
+        // Both of `--file -` and `-` path read stdin
+        let has_stdin = |id| {
+            matches
+                .get_many::<PathBuf>(id)
+                .is_some_and(|mut p| p.any(|p| p == Path::new("-")))
+        };
+        if has_stdin("file") {
+            let first_path = matches.get_one::<String>("PATTERN");
+            if has_stdin("PATH") || patterns_given && first_path.is_some_and(|p| p == "-") {
+                anyhow::bail!(
+                    "--file - cannot be used with '-' path since both of them read stdin"
+                );
+            }
+        }
+
+        let mut config = build_ripgrep_config(min_context, max_context, &matches)?;
+        config.encoding(encoding).search_zip(search_zip);
+        if let Some(sort) = sort {
+            config.sort(sort);
+        }
+
+        let pattern = matches.get_one::<String>("PATTERN");
+        // When patterns are given via -e or --file, the first positional argument is a path
+        let first_path = match pattern {
+            Some(path) if patterns_given => Some(Path::new(path)),
+            Some(pattern) => {
+                config.pattern(pattern.as_str());
+                None
+            }
+            None => None,
+        };
+        let rest = matches
             .get_many::<PathBuf>("PATH")
             .map(|p| p.map(PathBuf::as_path));
-        let config = build_ripgrep_config(min_context, max_context, &matches)?;
+        let paths = if first_path.is_none() && rest.is_none() {
+            None
+        } else {
+            Some(first_path.into_iter().chain(rest.into_iter().flatten()))
+        };
+
+        #[cfg(feature = "bat-printer")]
+        if printer_kind == PrinterKind::Bat && matches.contains_id("replace") {
+            anyhow::bail!("--replace option is only available for syntect printer since bat does not support rendering replacements");
+        }
 
         #[cfg(feature = "syntect-printer")]
         if printer_kind == PrinterKind::Syntect {
             let printer = SyntectPrinter::with_stdout(printer_opts)?;
-            return ripgrep::grep(printer, pattern, paths, config);
+            return ripgrep::grep_with_summary(printer, paths, config).map(report_summary);
         }
 
         #[cfg(feature = "bat-printer")]
         if printer_kind == PrinterKind::Bat {
             let printer = std::sync::Mutex::new(BatPrinter::new(printer_opts));
-            return ripgrep::grep(printer, pattern, paths, config);
+            return ripgrep::grep_with_summary(printer, paths, config).map(report_summary);
         }
 
         unreachable!();
@@ -609,9 +1104,20 @@ fn app() -> Result<bool> {
         use hgrep::printer::Printer;
         use rayon::prelude::*;
         let printer = SyntectPrinter::with_stdout(printer_opts)?;
+        if let Some(sort) = sort {
+            let matches = read_sorted_grep_lines(sort)?;
+            let files = Files::new(matches.into_iter().map(Ok), min_context, max_context)
+                .encoding(encoding)
+                .decompress(search_zip);
+            return hgrep::sort::print_in_order(&printer, files, |file| {
+                Ok(vec![printer.render(file?)?])
+            });
+        }
         return io::BufReader::new(io::stdin())
             .grep_lines()
             .chunks_per_file(min_context, max_context)
+            .encoding(encoding)
+            .decompress(search_zip)
             .par_bridge()
             .map(|file| {
                 printer.print(file?)?;
@@ -627,9 +1133,14 @@ fn app() -> Result<bool> {
         // XXX: io::stdin().lock() is not available since bat's implementation internally takes lock of stdin
         // *even if* it does not use stdin.
         // https://github.com/sharkdp/bat/issues/1902
-        for f in io::BufReader::new(io::stdin())
-            .grep_lines()
-            .chunks_per_file(min_context, max_context)
+        let matches: Box<dyn Iterator<Item = Result<GrepMatch>>> = if let Some(sort) = sort {
+            Box::new(read_sorted_grep_lines(sort)?.into_iter().map(Ok))
+        } else {
+            Box::new(io::BufReader::new(io::stdin()).grep_lines())
+        };
+        for f in Files::new(matches, min_context, max_context)
+            .encoding(encoding)
+            .decompress(search_zip)
         {
             printer.print(f?)?;
             found = true;
@@ -640,6 +1151,23 @@ fn app() -> Result<bool> {
     unreachable!();
 }
 
+// Read all grep results from stdin and sort them by their files. Matches in the same file keep their order
+fn read_sorted_grep_lines(sort: Sort) -> Result<Vec<GrepMatch>> {
+    let mut matches = io::BufReader::new(io::stdin())
+        .grep_lines()
+        .collect::<Result<Vec<_>>>()?;
+    sort.sort_by_file(&mut matches, |m| m.path.as_path());
+    Ok(matches)
+}
+
+#[cfg(feature = "ripgrep")]
+fn report_summary(summary: ripgrep::Summary) -> bool {
+    if summary.truncated {
+        eprintln!("\x1b[1;93mwarning:\x1b[0m Results were truncated since more matches were found than the limit by --max-total or --max-files");
+    }
+    summary.found
+}
+
 fn main() {
     #[cfg(windows)]
     {
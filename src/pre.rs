@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use grep_cli::CommandReader;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+// Preprocessor command given with `--pre`. The command is run for each searched file and its stdout is searched and
+// printed instead of the file contents as ripgrep's `--pre` does. It is useful for converting PDFs or documents into
+// plain text
+#[cfg_attr(test, derive(Debug))]
+#[derive(Clone)]
+pub struct PreCommand {
+    command: PathBuf,
+    // Globs given with `--pre-glob`. When it is `None`, all files are preprocessed
+    globs: Option<Arc<GlobSet>>,
+}
+
+impl PreCommand {
+    pub fn new<'a>(command: &Path, globs: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let command = grep_cli::resolve_binary(command)
+            .with_context(|| format!("could not resolve preprocessor command {:?}", command))?;
+        let mut builder = GlobSetBuilder::new();
+        let mut empty = true;
+        for glob in globs {
+            let glob = Glob::new(glob)
+                .with_context(|| format!("invalid glob {:?} for preprocessor command", glob))?;
+            builder.add(glob);
+            empty = false;
+        }
+        let globs = if empty {
+            None
+        } else {
+            Some(Arc::new(builder.build()?))
+        };
+        Ok(Self { command, globs })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        match &self.globs {
+            Some(globs) => globs.is_match(path),
+            None => true,
+        }
+    }
+
+    // Run the command for the file. The file path is passed as the first argument and the file contents are given
+    // via stdin. Stdout of the command can be read from the returned reader
+    pub fn open(&self, path: &Path) -> io::Result<CommandReader> {
+        let file = fs::File::open(path)?;
+        let mut cmd = Command::new(&self.command);
+        cmd.arg(path).stdin(Stdio::from(file));
+        CommandReader::new(&mut cmd).map_err(|err| {
+            io::Error::other(format!(
+                "preprocessor command {:?} failed for {:?}: {}",
+                self.command, path, err,
+            ))
+        })
+    }
+
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut reader = self.open(path)?;
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    fn upper_command() -> PathBuf {
+        Path::new("testdata").join("pre").join("upper.sh")
+    }
+
+    #[test]
+    fn test_matches() {
+        let pre = PreCommand::new(&upper_command(), iter::empty()).unwrap();
+        assert!(pre.matches(Path::new("foo.txt")));
+        assert!(pre.matches(Path::new("dir/foo.pdf")));
+
+        let pre = PreCommand::new(&upper_command(), ["*.pdf", "*.docx"].iter().copied()).unwrap();
+        assert!(pre.matches(Path::new("foo.pdf")));
+        assert!(pre.matches(Path::new("dir/foo.docx")));
+        assert!(!pre.matches(Path::new("foo.txt")));
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        let err = PreCommand::new(&upper_command(), iter::once("{foo")).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(msg.contains("invalid glob"), "message={:?}", msg);
+    }
+
+    #[test]
+    fn test_read() {
+        let pre = PreCommand::new(&upper_command(), iter::empty()).unwrap();
+        let path = Path::new("testdata").join("pre").join("notes.txt");
+        let got = pre.read(&path).unwrap();
+        let want = fs::read_to_string(&path).unwrap().to_uppercase();
+        assert_eq!(String::from_utf8(got).unwrap(), want);
+    }
+
+    #[test]
+    fn test_command_failed() {
+        let pre = PreCommand::new(Path::new("false"), iter::empty()).unwrap();
+        let path = Path::new("testdata").join("pre").join("notes.txt");
+        assert!(pre.read(&path).is_err());
+    }
+}
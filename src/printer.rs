@@ -1,14 +1,25 @@
 use crate::chunk::File;
-use anyhow::Result;
+use anyhow::{Error, Result};
 use std::env;
+use std::io::{self, Write};
+use std::str::FromStr;
 use term::terminfo::TermInfo;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TextWrapMode {
     Char,
+    Word,
+    Truncate,
     Never,
 }
 
+// Lines annotated with `git blame` information
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlameLines {
+    Matched,
+    All,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TermColorSupport {
     True,
@@ -39,17 +50,123 @@ impl TermColorSupport {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TermColor {
+    Rgb(u8, u8, u8),
+    Ansi(u8), // 0..=15 for 16 colors and 16..=255 for 256 colors
+}
+
+impl FromStr for TermColor {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        const NAMES: [&str; 8] = [
+            "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+        ];
+
+        if let Some(hex) = input.strip_prefix('#') {
+            let digits = match hex.len() {
+                3 => hex.chars().flat_map(|c| [c, c]).collect(), // #rgb is a shorthand of #rrggbb
+                6 => hex.to_string(),
+                _ => anyhow::bail!(
+                    "Hex color must be in #rrggbb or #rgb format but got {:?}",
+                    input
+                ),
+            };
+            let rgb = u32::from_str_radix(&digits, 16)
+                .map_err(|e| anyhow::anyhow!("Could not parse hex color {:?}: {}", input, e))?;
+            return Ok(TermColor::Rgb(
+                (rgb >> 16) as u8,
+                (rgb >> 8) as u8,
+                rgb as u8,
+            ));
+        }
+
+        if let Ok(n) = input.parse() {
+            return Ok(TermColor::Ansi(n));
+        }
+
+        let lower = input.to_ascii_lowercase();
+        let (name, bright) = match lower.strip_prefix("bright-") {
+            Some(name) => (name, true),
+            None => (lower.as_str(), false),
+        };
+        if let Some(idx) = NAMES.iter().position(|n| *n == name) {
+            let idx = idx as u8;
+            return Ok(TermColor::Ansi(if bright { idx + 8 } else { idx }));
+        }
+
+        anyhow::bail!(
+            "Unknown color {:?}. Color must be #rrggbb, #rgb, ANSI color number (0..255) or color name such as 'yellow' or 'bright-red'",
+            input,
+        )
+    }
+}
+
+// Colors to override the palette derived from the theme. `None` means using the theme's color
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CustomColors {
+    pub match_bg: Option<TermColor>,
+    pub match_lnum_fg: Option<TermColor>,
+    pub region_fg: Option<TermColor>,
+    pub region_bg: Option<TermColor>,
+    pub gutter_fg: Option<TermColor>,
+}
+
+// Font style of matched regions
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct RegionStyle {
+    pub bold: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+impl FromStr for RegionStyle {
+    type Err = Error;
+
+    // Comma-separated list like "bold,underline"
+    fn from_str(input: &str) -> Result<Self> {
+        let mut style = Self::default();
+        for s in input.split(',').map(str::trim) {
+            if s.eq_ignore_ascii_case("bold") {
+                style.bold = true;
+            } else if s.eq_ignore_ascii_case("underline") {
+                style.underline = true;
+            } else if s.eq_ignore_ascii_case("inverse") {
+                style.inverse = true;
+            } else if !s.eq_ignore_ascii_case("none") {
+                anyhow::bail!(
+                    "Unknown region style {:?}. It must be one of 'bold', 'underline', 'inverse' or 'none'",
+                    s,
+                );
+            }
+        }
+        Ok(style)
+    }
+}
+
 pub struct PrinterOptions<'main> {
     pub tab_width: usize,
     pub theme: Option<&'main str>,
+    // Syntax name or file extension to highlight all files with instead of detecting syntax from file paths
+    pub language: Option<&'main str>,
     pub grid: bool,
     pub background_color: bool,
     pub color_support: TermColorSupport,
     pub term_width: u16,
     pub custom_assets: bool,
     pub text_wrap: TextWrapMode,
+    pub wrap_indent: bool,
+    pub max_columns: Option<u16>,
+    pub show_all: bool,
+    pub git_changes: bool,
+    pub blame: Option<BlameLines>,
     pub first_only: bool,
     pub ascii_lines: bool,
+    pub custom_colors: CustomColors,
+    pub region_style: RegionStyle,
+    // Parsing syntax starts at most this number of lines before each chunk instead of the beginning of the file
+    pub approx_highlight: Option<u64>,
 }
 
 impl<'main> Default for PrinterOptions<'main> {
@@ -58,19 +175,135 @@ impl<'main> Default for PrinterOptions<'main> {
         Self {
             tab_width: 4,
             theme: None,
+            language: None,
             grid: true,
             background_color: false,
             color_support: TermColorSupport::detect(),
             custom_assets: false,
             term_width: terminal_size().map(|(Width(w), _)| w).unwrap_or(80), // Note: `tput` returns 80 when tty is not found
             text_wrap: TextWrapMode::Char,
+            wrap_indent: false,
+            max_columns: None,
+            show_all: false,
+            git_changes: false,
+            blame: None,
             first_only: false,
             ascii_lines: false,
+            custom_colors: CustomColors::default(),
+            region_style: RegionStyle::default(),
+            approx_highlight: None,
         }
     }
 }
 
+// Output of a file rendered in advance with `Printer::render`
+pub enum Rendered {
+    Bytes(Vec<u8>),
+    // The printer does not render files in advance. The file is printed on writing the output
+    File(File),
+}
+
 // Trait to replace printer implementation for unit tests
 pub trait Printer {
     fn print(&self, file: File) -> Result<()>;
+
+    // Render the file without writing it so that files rendered in parallel can be written in a specific order with
+    // `write_rendered`. By default, nothing is rendered in advance and the file is printed on writing
+    fn render(&self, file: File) -> Result<Rendered> {
+        Ok(Rendered::File(file))
+    }
+
+    // By default, rendered bytes are written to stdout. Printers writing to other destinations should override this
+    fn write_rendered(&self, rendered: Rendered) -> Result<()> {
+        match rendered {
+            Rendered::File(file) => self.print(file),
+            Rendered::Bytes(buf) => {
+                let stdout = io::stdout();
+                let mut out = stdout.lock();
+                out.write_all(&buf)?;
+                Ok(out.flush()?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_term_color() {
+        let tests = [
+            ("#ff8000", TermColor::Rgb(0xff, 0x80, 0x00)),
+            ("#FF8000", TermColor::Rgb(0xff, 0x80, 0x00)),
+            ("#f80", TermColor::Rgb(0xff, 0x88, 0x00)),
+            ("0", TermColor::Ansi(0)),
+            ("214", TermColor::Ansi(214)),
+            ("yellow", TermColor::Ansi(3)),
+            ("Bright-Red", TermColor::Ansi(9)),
+        ];
+        for (input, want) in tests {
+            assert_eq!(
+                input.parse::<TermColor>().unwrap(),
+                want,
+                "input={:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_term_color_error() {
+        for input in [
+            "", "#", "#ff80", "#gggggg", "256", "-1", "orange", "bright-",
+        ] {
+            let err = input.parse::<TermColor>().unwrap_err();
+            let msg = format!("{}", err);
+            assert!(
+                msg.contains(&format!("{:?}", input)),
+                "input={:?} msg={:?}",
+                input,
+                msg
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_region_style() {
+        let tests = [
+            ("none", RegionStyle::default()),
+            (
+                "bold",
+                RegionStyle {
+                    bold: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "bold,underline,inverse",
+                RegionStyle {
+                    bold: true,
+                    underline: true,
+                    inverse: true,
+                },
+            ),
+            (
+                " Inverse , underline ",
+                RegionStyle {
+                    underline: true,
+                    inverse: true,
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (input, want) in tests {
+            assert_eq!(
+                input.parse::<RegionStyle>().unwrap(),
+                want,
+                "input={:?}",
+                input
+            );
+        }
+        "bold,italic".parse::<RegionStyle>().unwrap_err();
+    }
 }
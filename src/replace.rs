@@ -0,0 +1,226 @@
+use crate::chunk::LineMatch;
+use anyhow::Result;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+// Apply replaced lines of the line matches to the contents. Lines not in `lmats` are kept as-is. `lmats` must be
+// sorted by line number
+pub fn apply(contents: &[u8], lmats: &[LineMatch]) -> Vec<u8> {
+    let mut lmats = lmats.iter().peekable();
+    let mut ret = Vec::with_capacity(contents.len());
+    for (idx, line) in contents.split_inclusive(|b| *b == b'\n').enumerate() {
+        let lnum = idx as u64 + 1;
+        let replaced = match lmats.peek() {
+            Some(m) if m.line_number == lnum => lmats.next().unwrap().replaced.as_ref(),
+            _ => None,
+        };
+        let replaced = match replaced {
+            Some(r) => r,
+            None => {
+                ret.extend_from_slice(line);
+                continue;
+            }
+        };
+
+        // Keep the line terminator of the original line
+        let term = if line.ends_with(b"\r\n") {
+            &b"\r\n"[..]
+        } else if line.ends_with(b"\n") {
+            &b"\n"[..]
+        } else {
+            &b""[..]
+        };
+        for (i, r) in replaced.iter().enumerate() {
+            ret.extend_from_slice(&r.text);
+            if i + 1 < replaced.len() {
+                ret.extend_from_slice(if term.is_empty() { b"\n" } else { term });
+            } else {
+                ret.extend_from_slice(term);
+            }
+        }
+    }
+    ret
+}
+
+// Write the contents to the file atomically by renaming a temporary file in the same directory. Permissions of the
+// original file are preserved. When the path is a symbolic link, its target file is written
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let perms = fs::metadata(&path)?.permissions();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.hgrep-{}.tmp", name, process::id()));
+
+    let result = (|| {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp, perms)?;
+        fs::rename(&tmp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// Write the contents of the file replaced with the line matches. `contents` is the contents of the file at
+// searching. The file is not written when it was modified after searching or when it was transcoded from other
+// encoding since writing them back would break the file
+pub fn write(path: &Path, contents: &[u8], lmats: &[LineMatch]) -> Result<()> {
+    let current = fs::read(path)?;
+    if current != contents {
+        anyhow::bail!(
+            "Could not write replacements to {:?} since it was modified after searching or it is not encoded in UTF-8",
+            path,
+        );
+    }
+    write_atomic(path, &apply(contents, lmats))?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Answer {
+    Yes,  // Apply the replacements in the chunk
+    No,   // Skip the chunk
+    All,  // Apply the replacements in the chunk and all the following chunks
+    Quit, // Skip the chunk and all the following chunks
+}
+
+// Ask whether to apply the replacements in the chunk until a valid answer is given. EOF of the input is
+// regarded as quitting
+pub fn ask<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<Answer> {
+    loop {
+        write!(output, "Apply this replacement [y,n,a,q,?]? ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(Answer::Quit);
+        }
+        match line.trim() {
+            "y" | "Y" | "yes" => return Ok(Answer::Yes),
+            "n" | "N" | "no" => return Ok(Answer::No),
+            "a" | "A" | "all" => return Ok(Answer::All),
+            "q" | "Q" | "quit" => return Ok(Answer::Quit),
+            _ => writeln!(
+                output,
+                "y - apply this replacement\nn - skip this replacement\na - apply this and all the remaining replacements\nq - quit; do not apply this and the remaining replacements",
+            )?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grep::Replacement;
+    use std::env;
+    use std::io::Cursor;
+
+    fn lmat(line_number: u64, replaced: &[&str]) -> LineMatch {
+        let replaced = replaced
+            .iter()
+            .map(|s| Replacement {
+                text: s.as_bytes().to_vec(),
+                ranges: vec![],
+            })
+            .collect();
+        LineMatch {
+            line_number,
+            ranges: vec![],
+            replaced: Some(replaced),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let tests = [
+            ("a\nb\nc\n", vec![lmat(2, &["B"])], "a\nB\nc\n"),
+            ("a\r\nb\r\nc\r\n", vec![lmat(2, &["B"])], "a\r\nB\r\nc\r\n"),
+            ("a\nb\nc", vec![lmat(3, &["C"])], "a\nb\nC"),
+            ("a\nb\nc\n", vec![lmat(1, &["x", "y"])], "x\ny\nb\nc\n"),
+            ("a\nb\nc\n", vec![lmat(1, &[]), lmat(2, &["AB"])], "AB\nc\n"),
+            ("a\nb\nc\n", vec![lmat(2, &[])], "a\nc\n"),
+            (
+                "a\nb\nc\n",
+                vec![lmat(1, &["A"]), lmat(3, &["C"])],
+                "A\nb\nC\n",
+            ),
+            ("a\nb\nc\n", vec![LineMatch::lnum(2)], "a\nb\nc\n"),
+            ("a\nb\nc\n", vec![], "a\nb\nc\n"),
+        ];
+        for (contents, lmats, want) in tests.iter() {
+            let got = apply(contents.as_bytes(), lmats);
+            assert_eq!(
+                String::from_utf8(got).unwrap(),
+                *want,
+                "contents={:?}",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = env::temp_dir().join(format!("hgrep-replace-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.txt");
+        fs::write(&path, "a\nb\nc\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+        }
+
+        write(&path, b"a\nb\nc\n", &[lmat(2, &["B"])]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nB\nc\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o751);
+        }
+
+        // The file was modified after searching
+        let err = write(&path, b"a\nb\nc\n", &[lmat(2, &["X"])]).unwrap_err();
+        assert!(format!("{}", err).contains("modified"), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nB\nc\n");
+
+        // Temporary file is not left
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ask() {
+        let tests = [
+            ("y\n", Answer::Yes),
+            ("n\n", Answer::No),
+            ("a\n", Answer::All),
+            ("q\n", Answer::Quit),
+            ("yes\r\n", Answer::Yes),
+            ("?\nwhat\nn\n", Answer::No),
+            ("", Answer::Quit),
+        ];
+        for (input, want) in tests.iter() {
+            let mut output = vec![];
+            let got = ask(Cursor::new(input.as_bytes()), &mut output).unwrap();
+            assert_eq!(got, *want, "input={:?}", input);
+        }
+
+        let mut output = vec![];
+        ask(Cursor::new(b"?\ny\n"), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("y - apply this replacement"),
+            "{:?}",
+            output
+        );
+    }
+}
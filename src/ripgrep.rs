@@ -1,8 +1,13 @@
-use crate::chunk::Files;
-use crate::grep::GrepMatch;
-use crate::printer::Printer;
+use crate::archive;
+use crate::chunk::{Encoding, File, Files, LineMatch};
+use crate::decompress;
+use crate::grep::{GrepMatch, Replacement};
+use crate::pre::PreCommand;
+use crate::printer::{Printer, Rendered};
+use crate::replace::{self, Answer};
+use crate::sort::{self, Sort, SortKey};
 use anyhow::{Context, Result};
-use grep_matcher::{LineTerminator, Matcher};
+use grep_matcher::{Captures, LineTerminator, Matcher};
 use grep_pcre2::{RegexMatcher as Pcre2Matcher, RegexMatcherBuilder as Pcre2MatcherBuilder};
 use grep_regex::{RegexMatcher, RegexMatcherBuilder};
 use grep_searcher::{BinaryDetection, MmapChoice, Searcher, SearcherBuilder, Sink, SinkMatch};
@@ -12,13 +17,21 @@ use ignore::{Walk, WalkBuilder};
 use rayon::iter::ParallelBridge;
 use rayon::prelude::*;
 use std::env;
-use std::fs::File;
 use std::io;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 // Note: 'main is a lifetime of scope of main() function
 
+// Patterns are not identified for highlighting when there are too many patterns since one matcher is built for
+// each pattern
+const MAX_PATTERN_BRANCHES: usize = 64;
+
+// Contents of matched files larger than this are not kept while searching to bound memory usage. Such files are read
+// again on printing
+const MAX_KEPT_BYTES: usize = 4 * 1024 * 1024;
+
 fn parse_size(input: &str) -> Result<u64> {
     if input.is_empty() {
         anyhow::bail!("Size string must not be empty");
@@ -39,8 +52,50 @@ fn parse_size(input: &str) -> Result<u64> {
     Ok(u * mag)
 }
 
+// Combine all patterns into one alternation
+fn joined_patterns(patterns: &[String]) -> String {
+    if let [pat] = patterns {
+        return pat.clone();
+    }
+    let pats: Vec<_> = patterns.iter().map(|p| format!("(?:{})", p)).collect();
+    pats.join("|")
+}
+
+// Split the regular expression into branches of its top-level alternation. For example, `foo|ba(r|z)` is split into
+// `foo` and `ba(r|z)`. `|` in groups, character classes and escape sequences is not a separator
+fn split_alternation(pat: &str) -> Vec<String> {
+    let mut branches = vec![];
+    let mut start = 0;
+    let (mut groups, mut classes) = (0usize, 0usize);
+    let mut chars = pat.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                classes += 1;
+                // `]` at the start of a character class is a literal
+                chars.next_if(|(_, c)| *c == '^');
+                chars.next_if(|(_, c)| *c == ']');
+            }
+            ']' if classes > 0 => classes -= 1,
+            '(' if classes == 0 => groups += 1,
+            ')' if classes == 0 => groups = groups.saturating_sub(1),
+            '|' if classes == 0 && groups == 0 => {
+                branches.push(pat[start..i].to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    branches.push(pat[start..].to_string());
+    branches
+}
+
 #[derive(Default)]
 pub struct Config<'main> {
+    patterns: Vec<String>,
     min_context: u64,
     max_context: u64,
     no_ignore: bool,
@@ -57,6 +112,8 @@ pub struct Config<'main> {
     multiline_dotall: bool,
     mmap: bool,
     max_count: Option<u64>,
+    max_total: Option<u64>,
+    max_files: Option<u64>,
     max_depth: Option<usize>,
     max_filesize: Option<u64>,
     line_regexp: bool,
@@ -68,6 +125,19 @@ pub struct Config<'main> {
     no_unicode: bool,
     regex_size_limit: Option<usize>,
     dfa_size_limit: Option<usize>,
+    encoding: Encoding,
+    search_zip: bool,
+    search_archive: bool,
+    pre: Option<&'main Path>,
+    pre_globs: Box<[&'main str]>,
+    label: Option<&'main str>,
+    sort: Option<Sort>,
+    cancel: Option<Arc<AtomicBool>>,
+    replace: Option<&'main str>,
+    write: bool,
+    confirm: bool,
+    and_patterns: Vec<String>,
+    near: Option<u64>,
 }
 
 impl<'main> Config<'main> {
@@ -77,6 +147,27 @@ impl<'main> Config<'main> {
         config
     }
 
+    pub fn pattern(&mut self, pat: impl Into<String>) -> &mut Self {
+        self.patterns.push(pat.into());
+        self
+    }
+
+    // Read patterns from the file. Each line of the file is a pattern. "-" means reading stdin
+    pub fn pattern_file(&mut self, path: &Path) -> Result<&mut Self> {
+        let contents = if path == Path::new("-") {
+            let mut buf = String::new();
+            io::Read::read_to_string(&mut io::stdin(), &mut buf)?;
+            buf
+        } else {
+            std::fs::read_to_string(path)
+                .with_context(|| format!("could not read pattern file {:?}", path))?
+        };
+        for line in contents.lines() {
+            self.patterns.push(line.to_string());
+        }
+        Ok(self)
+    }
+
     pub fn min_context(&mut self, num: u64) -> &mut Self {
         self.min_context = num;
         self
@@ -172,11 +263,24 @@ impl<'main> Config<'main> {
         self
     }
 
+    // Limit the number of matching lines per file
     pub fn max_count(&mut self, num: u64) -> &mut Self {
         self.max_count = Some(num);
         self
     }
 
+    // Limit the total number of matching lines in all files. Searching stops when reaching the limit
+    pub fn max_total(&mut self, num: u64) -> &mut Self {
+        self.max_total = Some(num);
+        self
+    }
+
+    // Limit the number of files with matches. Searching stops when reaching the limit
+    pub fn max_files(&mut self, num: u64) -> &mut Self {
+        self.max_files = Some(num);
+        self
+    }
+
     pub fn max_depth(&mut self, num: usize) -> &mut Self {
         self.max_depth = Some(num);
         self
@@ -207,6 +311,78 @@ impl<'main> Config<'main> {
         self
     }
 
+    pub fn encoding(&mut self, encoding: Encoding) -> &mut Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn search_zip(&mut self, yes: bool) -> &mut Self {
+        self.search_zip = yes;
+        self
+    }
+
+    // Search files in archives such as `.zip`, `.jar` or `.tar.gz`. Each member is searched as a separate file
+    pub fn search_archive(&mut self, yes: bool) -> &mut Self {
+        self.search_archive = yes;
+        self
+    }
+
+    // Print files in the order. Files are still searched and rendered in parallel
+    pub fn sort(&mut self, sort: Sort) -> &mut Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    // Searching stops as soon as possible when the flag is set from another thread
+    pub fn cancel(&mut self, flag: Arc<AtomicBool>) -> &mut Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    // Name of stdin shown in the header. Stdin is searched when "-" is given as path
+    pub fn label(&mut self, label: &'main str) -> &mut Self {
+        self.label = Some(label);
+        self
+    }
+
+    // Command to preprocess each file. Its output is searched and printed instead of the file contents
+    pub fn pre(&mut self, command: &'main Path) -> &mut Self {
+        self.pre = Some(command);
+        self
+    }
+
+    // Globs to select files to be preprocessed by the `pre` command. All files are preprocessed when empty
+    pub fn pre_globs(&mut self, globs: impl Iterator<Item = &'main str>) -> &mut Self {
+        self.pre_globs = globs.collect();
+        self
+    }
+
+    pub fn replace(&mut self, template: &'main str) -> &mut Self {
+        self.replace = Some(template);
+        self
+    }
+
+    pub fn write(&mut self, yes: bool) -> &mut Self {
+        self.write = yes;
+        self
+    }
+
+    pub fn confirm(&mut self, yes: bool) -> &mut Self {
+        self.confirm = yes;
+        self
+    }
+
+    // Pattern which must also be found in the file (or near the matches with `near`) in addition to the main patterns
+    pub fn and_pattern(&mut self, pat: impl Into<String>) -> &mut Self {
+        self.and_patterns.push(pat.into());
+        self
+    }
+
+    pub fn near(&mut self, lines: u64) -> &mut Self {
+        self.near = Some(lines);
+        self
+    }
+
     pub fn one_file_system(&mut self, yes: bool) -> &mut Self {
         self.one_file_system = yes;
         self
@@ -262,10 +438,14 @@ impl<'main> Config<'main> {
             builder.add_custom_ignore_filename(".rgignore");
         }
 
+        if let Some(sort) = self.sort.filter(|s| s.key == SortKey::Path) {
+            builder.sort_by_file_path(move |a, b| sort.compare_paths(a, b));
+        }
+
         Ok(builder.build())
     }
 
-    fn build_regex_matcher(&self, pat: &str) -> Result<RegexMatcher> {
+    fn build_regex_matcher(&self, patterns: &[String]) -> Result<RegexMatcher> {
         let mut builder = RegexMatcherBuilder::new();
         builder
             .case_insensitive(self.case_insensitive)
@@ -294,19 +474,21 @@ impl<'main> Config<'main> {
         }
 
         Ok(if self.fixed_strings {
-            let mut s = regex::escape(pat);
+            let lits: Vec<_> = patterns.iter().map(|p| regex::escape(p)).collect();
             if self.line_regexp {
-                s = format!("^(?:{})$", s);
+                builder.build(&format!("^(?:{})$", lits.join("|")))?
+            } else {
+                // This uses Aho-Corasick algorithm for many literals
+                builder.build_literals(&lits)?
             }
-            builder.build(&s)?
         } else if self.line_regexp {
-            builder.build(&format!("^(?:{})$", pat))?
+            builder.build(&format!("^(?:{})$", joined_patterns(patterns)))?
         } else {
-            builder.build(pat)?
+            builder.build(&joined_patterns(patterns))?
         })
     }
 
-    fn build_pcre2_matcher(&self, pat: &str) -> Result<Pcre2Matcher> {
+    fn build_pcre2_matcher(&self, patterns: &[String]) -> Result<Pcre2Matcher> {
         let mut builder = Pcre2MatcherBuilder::new();
         builder
             .caseless(self.case_insensitive)
@@ -331,13 +513,66 @@ impl<'main> Config<'main> {
         }
 
         if self.line_regexp {
-            Ok(builder.build(&format!("^(?:{})$", pat))?)
+            Ok(builder.build(&format!("^(?:{})$", joined_patterns(patterns)))?)
+        } else {
+            Ok(builder.build(&joined_patterns(patterns))?)
+        }
+    }
+
+    // Patterns searched by the matcher. Patterns given with `--and` are also searched to highlight their regions
+    fn searched_patterns(&self) -> Vec<String> {
+        let pats = self.patterns.iter().chain(self.and_patterns.iter());
+        pats.cloned().collect()
+    }
+
+    // Patterns to identify which pattern matched each region so that regions are highlighted in different colors.
+    // Patterns given with `-e` or `--file` are used as-is and a single regular expression is split into branches of
+    // its top-level alternation when `split` is true. The main patterns are put in one branch when they are not
+    // identified. Patterns given with `--and` follow them. Empty means patterns are not identified
+    fn pattern_branches(&self, split: bool) -> Vec<Vec<String>> {
+        if self.invert_match {
+            return vec![]; // Inverted matches have no region
+        }
+        let main = match self.patterns.as_slice() {
+            [pat] if split && !self.fixed_strings => split_alternation(pat),
+            pats => pats.to_vec(),
+        };
+        let mut branches: Vec<_> = if 2 <= main.len() && main.len() <= MAX_PATTERN_BRANCHES {
+            main.into_iter().map(|p| vec![p]).collect()
         } else {
-            Ok(builder.build(pat)?)
+            vec![self.patterns.clone()]
+        };
+        branches.extend(self.and_patterns.iter().map(|p| vec![p.clone()]));
+        if branches.len() < 2 {
+            return vec![];
         }
+        branches
+    }
+
+    // Build one matcher for each pattern branch. Returns the matchers and the number of branches of the main patterns.
+    // When some branch split from the alternation cannot be built as a standalone pattern, the alternation is not split
+    fn build_branch_matchers<M>(
+        &self,
+        build: impl Fn(&[String]) -> Result<M>,
+    ) -> Result<(Vec<M>, usize)> {
+        let build_all = |split| -> Result<Vec<M>> {
+            self.pattern_branches(split)
+                .iter()
+                .map(|p| build(p))
+                .collect()
+        };
+        let matchers = match build_all(true) {
+            Ok(matchers) => matchers,
+            Err(_) => build_all(false)?,
+        };
+        let main = matchers
+            .len()
+            .saturating_sub(self.and_patterns.len())
+            .max(1);
+        Ok((matchers, main))
     }
 
-    fn build_searcher(&self) -> Searcher {
+    fn build_searcher(&self) -> Result<Searcher> {
         let mut builder = SearcherBuilder::new();
         let mmap = if self.mmap {
             unsafe { MmapChoice::auto() }
@@ -353,7 +588,26 @@ impl<'main> Config<'main> {
         if self.crlf {
             builder.line_terminator(LineTerminator::crlf());
         }
-        builder.build()
+        match self.encoding {
+            Encoding::Auto => {} // Searcher sniffs BOM by default
+            Encoding::None => {
+                builder.bom_sniffing(false);
+            }
+            Encoding::Label(enc) => {
+                let enc = grep_searcher::Encoding::new(enc.name())?;
+                builder.encoding(Some(enc));
+            }
+        }
+        Ok(builder.build())
+    }
+
+    fn build_pre_command(&self) -> Result<Option<PreCommand>> {
+        if let Some(command) = self.pre {
+            let globs = self.pre_globs.iter().copied();
+            Ok(Some(PreCommand::new(command, globs)?))
+        } else {
+            Ok(None)
+        }
     }
 
     fn build_types(&self) -> Result<Types> {
@@ -390,19 +644,57 @@ impl<'main> Config<'main> {
     }
 }
 
+// Result of searching files
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Summary {
+    // Some match was found
+    pub found: bool,
+    // Some results were not printed due to `--max-total` or `--max-files`
+    pub truncated: bool,
+}
+
 pub fn grep<'main, P: Printer + Sync>(
     printer: P,
-    pat: &str,
     paths: Option<impl Iterator<Item = &'main Path>>,
     config: Config<'main>,
 ) -> Result<bool> {
-    let entries = if let Some(paths) = paths {
-        config.build_walker(paths)?
-    } else {
-        let cwd = env::current_dir()?;
-        let paths = std::iter::once(cwd.as_path());
-        config.build_walker(paths)?
+    grep_with_summary(printer, paths, config).map(|s| s.found)
+}
+
+pub fn grep_with_summary<'main, P: Printer + Sync>(
+    printer: P,
+    paths: Option<impl Iterator<Item = &'main Path>>,
+    config: Config<'main>,
+) -> Result<Summary> {
+    if config.patterns.is_empty() {
+        return Ok(Summary::default()); // No pattern matches nothing like ripgrep
+    }
+
+    // "-" in paths means searching stdin like ripgrep
+    let mut stdin = false;
+    let paths: Option<Vec<_>> = paths.map(|paths| {
+        paths
+            .filter(|path| {
+                let is_stdin = *path == Path::new("-");
+                stdin |= is_stdin;
+                !is_stdin
+            })
+            .collect()
+    });
+    if stdin && config.write {
+        anyhow::bail!("Replacements cannot be written to stdin");
+    }
+
+    let entries = match paths {
+        Some(paths) if paths.is_empty() => None, // Only stdin is searched
+        Some(paths) => Some(config.build_walker(paths.into_iter())?),
+        None => {
+            let cwd = env::current_dir()?;
+            let paths = std::iter::once(cwd.as_path());
+            Some(config.build_walker(paths)?)
+        }
     };
+    let entries = entries.into_iter().flatten();
 
     let paths = entries.filter_map(|entry| match entry {
         Ok(entry) => {
@@ -416,31 +708,31 @@ pub fn grep<'main, P: Printer + Sync>(
     });
 
     if config.pcre2 {
-        Ripgrep::with_pcre2(pat, config, printer)?.grep(paths)
+        Ripgrep::with_pcre2(config, printer)?.grep(stdin, paths)
     } else {
-        Ripgrep::with_regex(pat, config, printer)?.grep(paths)
+        Ripgrep::with_regex(config, printer)?.grep(stdin, paths)
     }
 }
 
 #[derive(Default)]
 struct LineRegions<'a> {
-    ranges: &'a [(usize, usize)],
+    ranges: &'a [(usize, usize, usize)],
     offset: usize,
 }
 
 impl<'a> LineRegions<'a> {
-    fn new(ranges: &'a [(usize, usize)]) -> Self {
+    fn new(ranges: &'a [(usize, usize, usize)]) -> Self {
         Self { ranges, offset: 0 }
     }
 
-    fn line_ranges(&mut self, line_len: usize) -> Vec<(usize, usize)> {
+    fn line_ranges(&mut self, line_len: usize) -> Vec<(usize, usize, usize)> {
         // Invariant: self.ranges is sorted and not over-wrapped
         let line_start = self.offset;
         let line_end = line_start + line_len;
 
         let mut ret = vec![];
         let mut next_start_idx = 0;
-        for (idx, (range_start, range_end)) in self.ranges.iter().copied().enumerate() {
+        for (idx, (range_start, range_end, pattern)) in self.ranges.iter().copied().enumerate() {
             // ls < le < rs < re
             if line_end < range_start {
                 break;
@@ -467,7 +759,7 @@ impl<'a> LineRegions<'a> {
             };
 
             if start < end {
-                ret.push((start, end));
+                ret.push((start, end, pattern));
             }
         }
 
@@ -480,10 +772,300 @@ impl<'a> LineRegions<'a> {
     }
 }
 
+// Index of the pattern which matches the range of the haystack. `branches` are matchers built for each pattern. When
+// no pattern matches the range exactly, the first pattern matching at the start is chosen. Otherwise 0 is returned
+fn pattern_index<M: Matcher>(branches: &[M], haystack: &[u8], start: usize, end: usize) -> usize {
+    let found: Vec<_> = branches
+        .iter()
+        .map(|m| m.find_at(haystack, start).ok().flatten())
+        .collect();
+    found
+        .iter()
+        .position(|m| matches!(m, Some(m) if m.start() == start && m.end() == end))
+        .or_else(|| {
+            found
+                .iter()
+                .position(|m| matches!(m, Some(m) if m.start() == start))
+        })
+        .unwrap_or(0)
+}
+
+// Filter the matches so that all the pattern groups are found. `group` maps the index of the matched pattern to its
+// group. Without `near`, the matches are kept only when all the groups are found in them. With `near`, each matched
+// line is kept only when all the groups are found within the number of lines around it among the kept lines
+fn filter_pattern_groups(
+    mut matches: Vec<GrepMatch>,
+    num_groups: usize,
+    near: Option<u64>,
+    group: impl Fn(usize) -> usize,
+) -> Vec<GrepMatch> {
+    let groups: Vec<Vec<usize>> = matches
+        .iter()
+        .map(|m| {
+            let mut groups: Vec<_> = m.ranges.iter().map(|(_, _, p)| group(*p)).collect();
+            groups.sort_unstable();
+            groups.dedup();
+            groups
+        })
+        .collect();
+
+    let near = match near {
+        Some(near) => near,
+        None => {
+            let mut found = vec![false; num_groups];
+            for g in groups.iter().flatten() {
+                if let Some(f) = found.get_mut(*g) {
+                    *f = true;
+                }
+            }
+            return if found.iter().all(|f| *f) {
+                matches
+            } else {
+                vec![]
+            };
+        }
+    };
+
+    // Removing a line may make other lines unsatisfied. Repeat until no line is removed
+    let mut kept = vec![true; matches.len()];
+    loop {
+        // Line numbers of kept lines for each group. They are sorted since matches are sorted by line number
+        let mut lnums = vec![vec![]; num_groups];
+        for ((m, groups), _) in matches
+            .iter()
+            .zip(groups.iter())
+            .zip(kept.iter())
+            .filter(|(_, k)| **k)
+        {
+            for g in groups {
+                if let Some(l) = lnums.get_mut(*g) {
+                    l.push(m.line_number);
+                }
+            }
+        }
+
+        let mut changed = false;
+        for (m, k) in matches.iter().zip(kept.iter_mut()) {
+            if !*k {
+                continue;
+            }
+            let lnum = m.line_number;
+            let satisfied = lnums.iter().all(|ls: &Vec<u64>| {
+                let i = ls.partition_point(|l| *l + near < lnum);
+                matches!(ls.get(i), Some(l) if *l <= lnum + near)
+            });
+            if !satisfied {
+                *k = false;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut kept = kept.into_iter();
+    matches.retain(|_| kept.next().unwrap());
+    matches
+}
+
+// Replace matched parts in the bytes with the template and split the result into lines. Capture groups in the
+// template such as `$1` or `${name}` are expanded with the captured texts. Parts matched to `--and` patterns (the
+// branches after the first `main` ones) are not replaced
+fn replace_lines<M: Matcher>(
+    matcher: &M,
+    (branches, main): (&[M], usize),
+    bytes: &[u8],
+    template: &str,
+) -> io::Result<Vec<Replacement>> {
+    let mut caps = matcher
+        .new_captures()
+        .map_err(|e| io::Error::other(format!("{}", e)))?;
+    let mut dst = vec![];
+    let mut ranges = vec![];
+    matcher
+        .replace_with_captures(bytes, &mut caps, &mut dst, |caps, dst| {
+            let start = dst.len();
+            let pattern = match caps.get(0) {
+                Some(m) => {
+                    let pattern = pattern_index(branches, bytes, m.start(), m.end());
+                    if pattern >= main {
+                        dst.extend_from_slice(&bytes[m]);
+                        return true;
+                    }
+                    pattern
+                }
+                None => 0,
+            };
+            caps.interpolate(
+                |name| matcher.capture_index(name),
+                bytes,
+                template.as_bytes(),
+                dst,
+            );
+            ranges.push((start, dst.len(), pattern));
+            true
+        })
+        .map_err(|e| io::Error::other(format!("{}", e)))?;
+
+    let mut regions = LineRegions::new(&ranges);
+    let lines = dst
+        .split_inclusive(|b| *b == b'\n')
+        .map(|line| {
+            let mut ranges = regions.line_ranges(line.len());
+            let text = line.strip_suffix(b"\n").unwrap_or(line);
+            let text = text.strip_suffix(b"\r").unwrap_or(text);
+            for (_, end, _) in ranges.iter_mut() {
+                *end = (*end).min(text.len()); // Do not highlight line terminator
+            }
+            ranges.retain(|(start, end, _)| start < end);
+            Replacement {
+                text: text.to_vec(),
+                ranges,
+            }
+        })
+        .collect();
+    Ok(lines)
+}
+
+// Limits of search results across all files. They are shared by threads
+#[derive(Default)]
+struct Limits {
+    lines: Option<Mutex<u64>>, // Remaining number of matching lines
+    files: Option<Mutex<u64>>, // Remaining number of files with matches
+    truncated: AtomicBool,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Limits {
+    fn new(config: &Config<'_>) -> Self {
+        Self {
+            lines: config.max_total.map(Mutex::new),
+            files: config.max_files.map(Mutex::new),
+            truncated: AtomicBool::new(false),
+            cancel: config.cancel.clone(),
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        match &self.cancel {
+            Some(flag) => flag.load(Ordering::Relaxed),
+            None => false,
+        }
+    }
+
+    // Take the matches in a file from the limits after filtering them. Matches beyond the limit of lines are
+    // discarded. Returns false when no match is taken. Results are truncated only when some match is discarded.
+    // Note: AtomicU64 is not available since it does not provide fetch_saturating_sub
+    fn take(&self, matches: &mut Vec<GrepMatch>) -> bool {
+        if matches.is_empty() {
+            return false;
+        }
+        // Lock both in the same order so that files and lines are taken atomically
+        let mut files = self.files.as_ref().map(|r| r.lock().unwrap());
+        let mut lines = self.lines.as_ref().map(|r| r.lock().unwrap());
+        if files.as_deref() == Some(&0) || lines.as_deref() == Some(&0) {
+            self.truncated.store(true, Ordering::Relaxed);
+            return false;
+        }
+        if let Some(files) = files.as_deref_mut() {
+            *files -= 1;
+        }
+        if let Some(lines) = lines.as_deref_mut() {
+            if matches.len() as u64 > *lines {
+                matches.truncate(*lines as usize);
+                self.truncated.store(true, Ordering::Relaxed);
+            }
+            *lines -= matches.len() as u64;
+        }
+        true
+    }
+
+    // Check if the number of matching lines in a file is already beyond the limits. Since the limits only decrease,
+    // some of the lines will be discarded for sure
+    fn exceeded(&self, lines: usize) -> bool {
+        let remaining = |r: &Option<Mutex<u64>>| r.as_ref().map(|r| *r.lock().unwrap());
+        lines > 0 && remaining(&self.files) == Some(0)
+            || matches!(remaining(&self.lines), Some(r) if lines as u64 > r)
+    }
+
+    // Searching stops when some match was discarded due to the limits or the search was cancelled. Files are still
+    // searched after just reaching the limits to know whether the results are truncated
+    fn stopped(&self) -> bool {
+        self.cancelled() || self.truncated()
+    }
+
+    fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+}
+
+// Reader to keep the bytes read by the searcher so that the contents of a matched file can be handed to the printer.
+// Bytes are streamed to the searcher and the kept bytes are dropped once they exceed the size limit
+struct Recorder<R> {
+    inner: R,
+    kept: Option<Vec<u8>>,
+    max: usize,
+    eof: bool,
+}
+
+impl<R: io::Read> Recorder<R> {
+    fn new(inner: R, max: usize) -> Self {
+        Self {
+            inner,
+            kept: Some(vec![]),
+            max,
+            eof: false,
+        }
+    }
+
+    // Return the whole contents. The rest of contents are read when the searcher stopped in the middle of the
+    // file. None means the contents are too large to keep
+    fn into_contents(mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = vec![0; 64 * 1024];
+        while self.kept.is_some() && !self.eof {
+            match io::Read::read(&mut self, &mut buf) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+                Ok(_) => {}
+            }
+        }
+        Ok(self.kept)
+    }
+}
+
+impl<R: io::Read> io::Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.eof = true;
+        }
+        if let Some(kept) = &mut self.kept {
+            if kept.len() + n > self.max {
+                self.kept = None;
+            } else {
+                kept.extend_from_slice(&buf[..n]);
+            }
+        }
+        Ok(n)
+    }
+}
+
+// Matches found in a file. The contents read by the searcher are kept so that the file is not read again on printing
+struct Found {
+    matches: Vec<GrepMatch>,
+    loaded: Vec<(PathBuf, Vec<u8>)>,
+}
+
 struct Matches<'a, M: Matcher> {
-    count: &'a Option<Mutex<u64>>,
+    count: Option<u64>, // Remaining number of matching lines in the file
+    limits: &'a Limits,
+    filtered: bool, // Matches are filtered after searching the file
     path: PathBuf,
     matcher: &'a M,
+    branches: &'a [M],
+    main_branches: usize,
+    replace: Option<&'a str>,
     buf: Vec<GrepMatch>,
 }
 
@@ -491,14 +1073,13 @@ impl<'a, M: Matcher> Sink for Matches<'a, M> {
     type Error = io::Error;
 
     fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
-        if let Some(count) = &self.count {
-            // Note: AtomicU64 is not available since it does not provide fetch_saturating_sub
-            let mut c = count.lock().unwrap();
-            if *c == 0 {
-                return Ok(false);
-            }
-            *c -= 1;
+        if self.count == Some(0) || self.limits.stopped() {
+            return Ok(false);
+        }
+        if !self.filtered && self.limits.exceeded(self.buf.len()) {
+            return Ok(false); // Rest of matches would be discarded
         }
+        self.count = self.count.map(|c| c - 1);
 
         let line_number = mat.line_number().unwrap();
         let path = &self.path;
@@ -506,20 +1087,37 @@ impl<'a, M: Matcher> Sink for Matches<'a, M> {
         let mut ranges = vec![];
         self.matcher
             .find_iter(mat.bytes(), |m| {
-                ranges.push((m.start(), m.end()));
+                let pattern = pattern_index(self.branches, mat.bytes(), m.start(), m.end());
+                ranges.push((m.start(), m.end(), pattern));
                 true
             })
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}", e)))?;
+            .map_err(|e| io::Error::other(format!("{}", e)))?;
         let mut regions = LineRegions::new(&ranges);
 
-        let mut line_number = line_number;
-        for line in mat.lines() {
+        let mut replaced = match self.replace {
+            Some(template) => Some(replace_lines(
+                self.matcher,
+                (self.branches, self.main_branches),
+                mat.bytes(),
+                template,
+            )?),
+            None => None,
+        };
+
+        let mut lines = mat.lines().enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            // Replaced lines are put to the last line of the match
+            let replaced = if lines.peek().is_some() {
+                replaced.as_ref().map(|_| vec![])
+            } else {
+                replaced.take()
+            };
             self.buf.push(GrepMatch {
                 path: path.to_owned(),
-                line_number,
+                line_number: line_number + i as u64,
                 ranges: regions.line_ranges(line.len()),
+                replaced,
             });
-            line_number += 1;
         }
 
         Ok(true)
@@ -529,19 +1127,26 @@ impl<'a, M: Matcher> Sink for Matches<'a, M> {
 struct Ripgrep<'main, M: Matcher, P: Printer> {
     config: Config<'main>,
     matcher: M,
-    count: Option<Mutex<u64>>,
+    branches: Vec<M>, // Matchers for each pattern to identify which pattern matched the region
+    main_branches: usize, // Number of branches for the main patterns. Rest of branches are for `--and` patterns
+    pre: Option<PreCommand>,
+    limits: Limits,
     printer: P,
 }
 
 impl<'main, P: Printer + Sync> Ripgrep<'main, RegexMatcher, P> {
-    fn with_regex(pat: &str, config: Config<'main>, printer: P) -> Result<Self> {
-        Ok(Self::new(config.build_regex_matcher(pat)?, config, printer))
+    fn with_regex(config: Config<'main>, printer: P) -> Result<Self> {
+        let matcher = config.build_regex_matcher(&config.searched_patterns())?;
+        let branches = config.build_branch_matchers(|p| config.build_regex_matcher(p))?;
+        Self::new(matcher, branches, config, printer)
     }
 }
 
 impl<'main, P: Printer + Sync> Ripgrep<'main, Pcre2Matcher, P> {
-    fn with_pcre2(pat: &str, config: Config<'main>, printer: P) -> Result<Self> {
-        Ok(Self::new(config.build_pcre2_matcher(pat)?, config, printer))
+    fn with_pcre2(config: Config<'main>, printer: P) -> Result<Self> {
+        let matcher = config.build_pcre2_matcher(&config.searched_patterns())?;
+        let branches = config.build_branch_matchers(|p| config.build_pcre2_matcher(p))?;
+        Self::new(matcher, branches, config, printer)
     }
 }
 
@@ -550,96 +1155,382 @@ where
     M: Matcher + Sync,
     P: Printer + Sync,
 {
-    fn new(matcher: M, config: Config<'main>, printer: P) -> Self {
-        Self {
-            count: config.max_count.map(Mutex::new),
+    fn new(
+        matcher: M,
+        (branches, main_branches): (Vec<M>, usize),
+        config: Config<'main>,
+        printer: P,
+    ) -> Result<Self> {
+        Ok(Self {
+            limits: Limits::new(&config),
             matcher,
+            branches,
+            main_branches,
+            pre: config.build_pre_command()?,
             printer,
             config,
-        }
+        })
     }
 
-    // Return Result<Option<Vec<_>>> instead of Result<Vec<_>> to make the `filter_map` predicate easy
+    // Return Result<Option<_>> instead of Result<_> to make the `filter_map` predicate easy
     // in `grep()` method
-    fn search(&self, path: PathBuf) -> Result<Option<Vec<GrepMatch>>> {
-        if let Some(count) = &self.count {
-            if *count.lock().unwrap() == 0 {
-                return Ok(None);
+    fn search(&self, path: PathBuf) -> Result<Option<Found>> {
+        if self.limits.stopped() {
+            return Ok(None);
+        }
+
+        let pre = self.pre.as_ref().filter(|pre| pre.matches(&path));
+        if pre.is_none() && self.config.search_archive && archive::is_archive(&path) {
+            return self.search_archive(&path);
+        }
+
+        let compressed = self.config.search_zip && decompress::is_compressed(&path);
+        match pre {
+            Some(pre) => {
+                let reader = pre.open(&path)?;
+                self.search_reader(path, reader)
+            }
+            None if compressed => {
+                let reader = decompress::open(&path)?;
+                self.search_reader(path, reader)
+            }
+            None if self.config.mmap => self.search_file(path),
+            None => {
+                let reader = std::fs::File::open(&path)?;
+                self.search_reader(path, reader)
             }
         }
+    }
 
-        let file = File::open(&path)?;
-        let mut searcher = self.config.build_searcher();
-        let mut matches = Matches {
-            count: &self.count,
-            path,
-            matcher: &self.matcher,
-            buf: vec![],
+    // Search the contents streamed from the reader. Non-matching files are not kept. The contents of a matched file
+    // are kept in the result to print them unless they are too large
+    fn search_reader(&self, path: PathBuf, reader: impl io::Read) -> Result<Option<Found>> {
+        let mut searcher = self.config.build_searcher()?;
+        let mut matches = self.matches(path);
+        let mut reader = Recorder::new(reader, MAX_KEPT_BYTES);
+        searcher.search_reader(&self.matcher, &mut reader, &mut matches)?;
+        let path = matches.path;
+        let mut matches = self.filter_and_patterns(matches.buf);
+        if !self.limits.take(&mut matches) {
+            return Ok(None);
+        }
+        let loaded = match reader.into_contents()? {
+            Some(contents) => vec![(path, contents)],
+            None => vec![],
         };
+        Ok(Some(Found { matches, loaded }))
+    }
 
+    // Search the file with memory map. Contents of memory-mapped files cannot be handed to the printer so the file is
+    // read again on printing
+    fn search_file(&self, path: PathBuf) -> Result<Option<Found>> {
+        let mut searcher = self.config.build_searcher()?;
+        let mut matches = self.matches(path);
+        let file = std::fs::File::open(&matches.path)?;
         searcher.search_file(&self.matcher, &file, &mut matches)?;
-        if matches.buf.is_empty() {
+        let mut matches = self.filter_and_patterns(matches.buf);
+        if !self.limits.take(&mut matches) {
+            return Ok(None);
+        }
+        Ok(Some(Found {
+            matches,
+            loaded: vec![],
+        }))
+    }
+
+    // Search the contents which were already read. They are kept in the result to print them
+    fn search_loaded(&self, path: PathBuf, contents: Vec<u8>) -> Result<Option<Found>> {
+        let mut searcher = self.config.build_searcher()?;
+        let mut matches = self.matches(path);
+        searcher.search_slice(&self.matcher, &contents, &mut matches)?;
+        let path = matches.path;
+        let mut matches = self.filter_and_patterns(matches.buf);
+        if !self.limits.take(&mut matches) {
             return Ok(None);
         }
+        Ok(Some(Found {
+            matches,
+            loaded: vec![(path, contents)],
+        }))
+    }
 
-        Ok(Some(matches.buf))
+    // Search the contents read from stdin. They are printed with the label as their path
+    fn search_stdin(&self) -> Result<bool> {
+        let mut contents = vec![];
+        io::Read::read_to_end(&mut io::stdin().lock(), &mut contents)?;
+        let path = PathBuf::from(self.config.label.unwrap_or("<stdin>"));
+        self.search_contents(path, contents)
     }
 
-    fn print_matches(&self, matches: Vec<GrepMatch>) -> Result<bool> {
-        let (min, max) = (self.config.min_context, self.config.max_context);
-        let mut found = false;
-        for file in Files::new(matches.into_iter().map(Ok), min, max) {
-            self.printer.print(file?)?;
-            found = true;
+    fn search_contents(&self, path: PathBuf, contents: Vec<u8>) -> Result<bool> {
+        match self.search_loaded(path, contents)? {
+            Some(found) => self.print_found(found),
+            None => Ok(false),
         }
-        Ok(found)
     }
 
-    fn grep<I>(&self, paths: I) -> Result<bool>
-    where
-        I: Iterator<Item = Result<PathBuf>> + Send,
-    {
-        paths
-            .par_bridge()
-            .filter_map(|path| match path {
-                Ok(path) => self.search(path).transpose(),
-                Err(err) => Some(Err(err)),
-            })
-            .map(|matches| self.print_matches(matches?))
-            .try_reduce(|| false, |a, b| Ok(a || b))
+    fn matches(&self, path: PathBuf) -> Matches<'_, M> {
+        Matches {
+            count: self.config.max_count,
+            limits: &self.limits,
+            filtered: !self.config.and_patterns.is_empty() && !self.config.invert_match,
+            path,
+            matcher: &self.matcher,
+            branches: &self.branches,
+            main_branches: self.main_branches,
+            // Inverted matches have nothing to be replaced
+            replace: self.config.replace.filter(|_| !self.config.invert_match),
+            buf: vec![],
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::chunk::{File, LineMatch};
-    use crate::test::{read_all_expected_chunks, read_expected_chunks};
-    use pretty_assertions::assert_eq;
-    use regex::Regex;
-    use std::ffi::OsStr;
-    use std::fs;
-    use std::iter;
-    use std::mem;
-    use std::path::Path;
-    use std::sync::Mutex;
 
-    #[derive(Default)]
-    struct DummyPrinter(Mutex<Vec<File>>);
-    impl Printer for &DummyPrinter {
-        fn print(&self, file: File) -> Result<()> {
-            self.0.lock().unwrap().push(file);
+    // Search each member of the archive. Matches in all the members are returned at once. Their paths are
+    // `{archive}!/{member}`. Contents of the matched members are kept so that the archive is not extracted again
+    fn search_archive(&self, path: &Path) -> Result<Option<Found>> {
+        let mut searcher = self.config.build_searcher()?;
+        let mut found = Found {
+            matches: vec![],
+            loaded: vec![],
+        };
+        archive::for_each_member(path, |member, contents| {
+            let mut matches = self.matches(archive::member_path(path, member));
+            searcher.search_slice(&self.matcher, contents, &mut matches)?;
+            let member = matches.path;
+            let mut matches = self.filter_and_patterns(matches.buf);
+            if self.limits.take(&mut matches) {
+                found.matches.extend(matches);
+                found.loaded.push((member, contents.to_vec()));
+            }
             Ok(())
+        })
+        .with_context(|| format!("could not search archive {:?}", path))?;
+
+        if found.matches.is_empty() {
+            return Ok(None);
         }
+        Ok(Some(found))
     }
 
-    impl DummyPrinter {
+    // Keep only the matches satisfying all the patterns given with `--and`. The main patterns are one group and each
+    // pattern given with `--and` is its own group
+    fn filter_and_patterns(&self, matches: Vec<GrepMatch>) -> Vec<GrepMatch> {
+        if self.config.and_patterns.is_empty() || self.config.invert_match || matches.is_empty() {
+            return matches;
+        }
+        let num_groups = self.config.and_patterns.len() + 1;
+        let main = self.main_branches;
+        filter_pattern_groups(matches, num_groups, self.config.near, |p| {
+            (p + 1).saturating_sub(main)
+        })
+    }
+
+    fn print_found(&self, found: Found) -> Result<bool> {
+        self.each_file(found, |file| self.printer.print(file))
+    }
+
+    fn render_found(&self, found: Found) -> Result<Vec<Rendered>> {
+        let mut rendered = vec![];
+        self.each_file(found, |file| {
+            rendered.push(self.printer.render(file)?);
+            Ok(())
+        })?;
+        Ok(rendered)
+    }
+
+    // Split the matches into files and call the callback with each file. Replacements are written to the file after
+    // the callback when `--write` is enabled
+    fn each_file<F>(&self, found: Found, mut f: F) -> Result<bool>
+    where
+        F: FnMut(File) -> Result<()>,
+    {
+        let mut printed = false;
+        for file in self.files(found) {
+            let file = file?;
+            let written = if self.config.write {
+                Some((
+                    file.path.clone(),
+                    file.contents.clone(),
+                    file.line_matches.clone(),
+                ))
+            } else {
+                None
+            };
+            f(file)?;
+            if let Some((path, contents, lmats)) = written {
+                replace::write(&path, &contents, &lmats)?;
+            }
+            printed = true;
+        }
+        Ok(printed)
+    }
+
+    fn files(&self, found: Found) -> Files<impl Iterator<Item = Result<GrepMatch>>> {
+        let (min, max) = (self.config.min_context, self.config.max_context);
+        let mut files = Files::new(found.matches.into_iter().map(Ok), min, max)
+            .encoding(self.config.encoding)
+            .decompress(self.config.search_zip)
+            .pre(self.pre.clone())
+            .archive(self.config.search_archive);
+        for (path, contents) in found.loaded {
+            files = files.contents(path, contents);
+        }
+        files
+    }
+
+    // Print each chunk and ask whether to apply its replacements. Accepted replacements are written to the file
+    // after asking all chunks in the file. Returns false when quitting
+    fn confirm_matches(
+        &self,
+        found: Found,
+        input: &mut impl io::BufRead,
+        all: &mut bool,
+    ) -> Result<bool> {
+        for file in self.files(found) {
+            let file = file?;
+            let mut accepted = vec![];
+            let mut quit = false;
+            for (start, end) in file.chunks.iter().copied() {
+                let lmats: Vec<LineMatch> = file
+                    .line_matches
+                    .iter()
+                    .filter(|m| start <= m.line_number && m.line_number <= end)
+                    .cloned()
+                    .collect();
+                // The preview shares the contents with the file instead of copying them for each chunk
+                let chunk = File {
+                    path: file.path.clone(),
+                    line_matches: lmats.clone().into_boxed_slice(),
+                    chunks: Box::new([(start, end)]),
+                    contents: file.contents.clone(),
+                };
+                self.printer.print(chunk)?;
+                if !*all {
+                    match replace::ask(&mut *input, io::stderr())? {
+                        Answer::Yes => {}
+                        Answer::No => continue,
+                        Answer::All => *all = true,
+                        Answer::Quit => {
+                            quit = true;
+                            break;
+                        }
+                    }
+                }
+                accepted.extend(lmats);
+            }
+            if !accepted.is_empty() {
+                replace::write(&file.path, &file.contents, &accepted)?;
+            }
+            if quit {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Chunks are searched and printed sequentially on confirming replacements since prompts must not be interleaved
+    fn grep_confirm<I>(&self, paths: I) -> Result<bool>
+    where
+        I: Iterator<Item = Result<PathBuf>>,
+    {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let mut all = false;
+        let mut found = false;
+        for path in paths {
+            if let Some(matches) = self.search(path?)? {
+                found = true;
+                if !self.confirm_matches(matches, &mut input, &mut all)? {
+                    break;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    // Search files in parallel and print them in the order of the paths
+    fn grep_in_order<I>(&self, paths: I) -> Result<bool>
+    where
+        I: Iterator<Item = Result<PathBuf>> + Send,
+    {
+        sort::print_in_order(&self.printer, paths, |path| match self.search(path?)? {
+            Some(found) => self.render_found(found),
+            None => Ok(vec![]),
+        })
+    }
+
+    fn grep_paths<I>(&self, paths: I) -> Result<bool>
+    where
+        I: Iterator<Item = Result<PathBuf>> + Send,
+    {
+        if self.config.write && self.config.confirm {
+            return self.grep_confirm(paths);
+        }
+        if self.config.sort.is_some() {
+            return self.grep_in_order(paths);
+        }
+        paths
+            .par_bridge()
+            .filter_map(|path| match path {
+                Ok(path) => self.search(path).transpose(),
+                Err(err) => Some(Err(err)),
+            })
+            .map(|found| self.print_found(found?))
+            .try_reduce(|| false, |a, b| Ok(a || b))
+    }
+
+    fn grep<I>(&self, stdin: bool, paths: I) -> Result<Summary>
+    where
+        I: Iterator<Item = Result<PathBuf>> + Send,
+    {
+        let found = stdin && self.search_stdin()?;
+        // Stop walking directories once the limits of results are reached
+        let paths = paths.take_while(|_| !self.limits.stopped());
+        let found_in_files = match self.config.sort {
+            // Timestamps of all the files are necessary to sort them. Paths are sorted by the walker
+            Some(sort) if sort.key != SortKey::Path => {
+                let mut paths = paths.collect::<Result<Vec<_>>>()?;
+                sort.sort_by_file(&mut paths, PathBuf::as_path);
+                self.grep_paths(paths.into_iter().map(Ok))?
+            }
+            _ => self.grep_paths(paths)?,
+        };
+        Ok(Summary {
+            found: found || found_in_files,
+            truncated: self.limits.truncated(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{File, LineMatch};
+    use crate::test::{read_all_expected_chunks, read_expected_chunks};
+    use pretty_assertions::assert_eq;
+    use regex::Regex;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::iter;
+    use std::mem;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct DummyPrinter(Mutex<Vec<File>>);
+    impl Printer for &DummyPrinter {
+        fn print(&self, file: File) -> Result<()> {
+            self.0.lock().unwrap().push(file);
+            Ok(())
+        }
+    }
+
+    impl DummyPrinter {
         fn validate_and_remove_region_ranges(&mut self) {
             for file in self.0.get_mut().unwrap().iter_mut() {
                 let lines: Vec<_> = file.contents.split_inclusive(|b| *b == b'\n').collect();
                 for lmat in file.line_matches.iter_mut() {
                     // Reset `lmat.range` to None since ranges in `expected` are `None`
-                    let (start, end) = mem::take(&mut lmat.ranges)[0];
+                    let (start, end, _) = mem::take(&mut lmat.ranges)[0];
                     let line = lines[lmat.line_number as usize - 1];
                     let matched_part = &line[start..end];
                     assert_eq!(
@@ -678,7 +1569,9 @@ mod tests {
             let pat = r"\*$";
             let file = dir.join(format!("{}.in", input));
             let paths = iter::once(file.as_path());
-            let found = grep(&printer, pat, Some(paths), Config::new(3, 6)).unwrap();
+            let mut config = Config::new(3, 6);
+            config.pattern(pat);
+            let found = grep(&printer, Some(paths), config).unwrap();
             let expected = read_expected_chunks(&dir, input)
                 .map(|f| vec![f])
                 .unwrap_or_else(Vec::new);
@@ -704,7 +1597,9 @@ mod tests {
             .collect::<Vec<_>>();
         let paths = paths.iter().map(AsRef::as_ref);
 
-        let found = grep(&printer, pat, Some(paths), Config::new(3, 6)).unwrap();
+        let mut config = Config::new(3, 6);
+        config.pattern(pat);
+        let found = grep(&printer, Some(paths), config).unwrap();
 
         printer.validate_and_remove_region_ranges();
 
@@ -718,13 +1613,177 @@ mod tests {
         assert_eq!(expected, got);
     }
 
+    #[test]
+    fn test_grep_sorted() {
+        let dir = Path::new("testdata").join("chunk");
+        let want = {
+            let inputs = read_all_inputs(&dir);
+            let mut files = read_all_expected_chunks(&dir, &inputs)
+                .into_iter()
+                .map(|f| f.path)
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        for key in [SortKey::Path, SortKey::Modified] {
+            for reverse in [false, true] {
+                let printer = DummyPrinter::default();
+                let paths = iter::once(dir.as_path());
+                let mut config = Config::new(3, 6);
+                config
+                    .pattern(r"\*$")
+                    .globs(iter::once("*.in"))
+                    .sort(Sort::new(key, reverse));
+                let found = grep(&printer, Some(paths), config).unwrap();
+                assert!(found);
+
+                let got: Vec<_> = printer
+                    .0
+                    .into_inner()
+                    .unwrap()
+                    .into_iter()
+                    .map(|f| f.path)
+                    .collect();
+                let mut want = want.clone();
+                if key == SortKey::Modified {
+                    let sort = Sort::new(key, reverse);
+                    sort.sort_by_file(&mut want, PathBuf::as_path);
+                } else if reverse {
+                    want.reverse();
+                }
+                assert_eq!(got, want, "key={:?} reverse={:?}", key, reverse);
+            }
+        }
+    }
+
+    // Files are searched in parallel so which file reaches the limits first is not deterministic
+    fn grep_limited(mut config: Config<'_>, names: &[&str]) -> (Summary, Vec<(PathBuf, Vec<u64>)>) {
+        let dir = Path::new("testdata").join("archive");
+        let paths: Vec<_> = names.iter().map(|n| dir.join(n)).collect();
+        let printer = DummyPrinter::default();
+        config.pattern("password");
+        let paths = paths.iter().map(PathBuf::as_path);
+        let summary = grep_with_summary(&printer, Some(paths), config).unwrap();
+        let mut files: Vec<_> = printer
+            .0
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|f| {
+                let lnums = f.line_matches.iter().map(|m| m.line_number).collect();
+                (f.path, lnums)
+            })
+            .collect();
+        files.sort();
+        (summary, files)
+    }
+
+    #[test]
+    fn test_max_count_per_file() {
+        let dir = Path::new("testdata").join("archive");
+        let mut config = Config::new(3, 6);
+        config.max_count(1);
+        let (summary, files) = grep_limited(config, &["main.rs", "application.yml"]);
+        let want = Summary {
+            found: true,
+            truncated: false,
+        };
+        assert_eq!(summary, want);
+        let want = vec![
+            (dir.join("application.yml"), vec![7]),
+            (dir.join("main.rs"), vec![2]),
+        ];
+        assert_eq!(files, want);
+    }
+
+    #[test]
+    fn test_max_total() {
+        let dir = Path::new("testdata").join("archive");
+        let mut config = Config::new(3, 6);
+        config.max_total(1);
+        let (summary, files) = grep_limited(config, &["main.rs"]);
+        let want = Summary {
+            found: true,
+            truncated: true,
+        };
+        assert_eq!(summary, want);
+        assert_eq!(files, vec![(dir.join("main.rs"), vec![2])]);
+
+        let mut config = Config::new(3, 6);
+        config.max_total(3);
+        let (summary, files) = grep_limited(config, &["main.rs", "application.yml"]);
+        let want = Summary {
+            found: true,
+            truncated: false,
+        };
+        assert_eq!(summary, want);
+        let want = vec![
+            (dir.join("application.yml"), vec![7]),
+            (dir.join("main.rs"), vec![2, 3]),
+        ];
+        assert_eq!(files, want);
+    }
+
+    #[test]
+    fn test_max_files() {
+        let mut config = Config::new(3, 6);
+        config.max_files(1);
+        let (summary, files) = grep_limited(config, &["main.rs", "application.yml"]);
+        let want = Summary {
+            found: true,
+            truncated: true,
+        };
+        assert_eq!(summary, want);
+        assert_eq!(files.len(), 1, "files={:?}", files);
+    }
+
+    #[test]
+    fn test_limits_reached_exactly() {
+        let dir = Path::new("testdata").join("archive");
+        let not_truncated = Summary {
+            found: true,
+            truncated: false,
+        };
+
+        let mut config = Config::new(3, 6);
+        config.max_total(2);
+        let (summary, files) = grep_limited(config, &["main.rs"]);
+        assert_eq!(summary, not_truncated);
+        assert_eq!(files, vec![(dir.join("main.rs"), vec![2, 3])]);
+
+        // Rest of files without matches are not truncated
+        let mut config = Config::new(3, 6);
+        config.max_files(1).max_total(2);
+        let (summary, files) = grep_limited(config, &["main.rs", "bundle.tar", "app.jar"]);
+        assert_eq!(summary, not_truncated);
+        assert_eq!(files, vec![(dir.join("main.rs"), vec![2, 3])]);
+    }
+
+    #[test]
+    fn test_limits_ignore_rejected_files() {
+        let dir = Path::new("testdata").join("archive");
+        // application.yml is rejected since it does not contain the pattern given with --and
+        let mut config = Config::new(3, 6);
+        config.and_pattern("println").max_total(2).max_files(1);
+        let (summary, files) = grep_limited(config, &["application.yml", "main.rs"]);
+        let want = Summary {
+            found: true,
+            truncated: false,
+        };
+        assert_eq!(summary, want);
+        assert_eq!(files, vec![(dir.join("main.rs"), vec![2, 3])]);
+    }
+
     #[test]
     fn test_grep_no_match_found() {
         let path = Path::new("testdata").join("chunk").join("single_max.in");
         let paths = iter::once(path.as_path());
         let printer = DummyPrinter::default();
         let pat = "^this does not match to any line!!!!!!$";
-        let found = grep(&printer, pat, Some(paths), Config::new(3, 6)).unwrap();
+        let mut config = Config::new(3, 6);
+        config.pattern(pat);
+        let found = grep(&printer, Some(paths), config).unwrap();
         let files = printer.0.into_inner().unwrap();
         assert!(!found, "result: {:?}", files);
         assert!(files.is_empty(), "result: {:?}", files);
@@ -741,7 +1800,9 @@ mod tests {
             let paths = iter::once(path.as_path());
             let printer = DummyPrinter::default();
             let pat = ".*";
-            grep(&printer, pat, Some(paths), Config::new(3, 6)).unwrap_err();
+            let mut config = Config::new(3, 6);
+            config.pattern(pat);
+            grep(&printer, Some(paths), config).unwrap_err();
             assert!(printer.0.into_inner().unwrap().is_empty());
         }
     }
@@ -758,7 +1819,9 @@ mod tests {
         let path = Path::new("testdata").join("chunk").join("single_max.in");
         let paths = iter::once(path.as_path());
         let pat = ".*";
-        let err = grep(ErrorPrinter, pat, Some(paths), Config::new(3, 6)).unwrap_err();
+        let mut config = Config::new(3, 6);
+        config.pattern(pat);
+        let err = grep(ErrorPrinter, Some(paths), config).unwrap_err();
         let msg = format!("{}", err);
         assert_eq!(msg, "dummy error");
     }
@@ -776,9 +1839,10 @@ mod tests {
         }
     }
 
-    fn read_ripgrep_expected(file_name: &str) -> File {
+    fn read_ripgrep_expected(file_name: &str, encoding: Encoding) -> File {
         let path = Path::new("testdata").join("ripgrep").join(file_name);
-        let contents = fs::read_to_string(&path).unwrap();
+        let contents = encoding.decode(fs::read(&path).unwrap()).unwrap();
+        let contents = String::from_utf8(contents).unwrap();
         let mut lines = contents.lines();
 
         let mut chunks = vec![];
@@ -812,10 +1876,9 @@ mod tests {
                 let line_number = s.next().unwrap().parse().unwrap();
                 let start = s.next().unwrap().parse().unwrap();
                 let end = s.next().unwrap().parse().unwrap();
-                line_matches.push(LineMatch {
-                    line_number,
-                    ranges: vec![(start, end)],
-                })
+                // Index of the matched pattern is optional
+                let pattern = s.next().map(|p| p.parse().unwrap()).unwrap_or(0);
+                line_matches.push(LineMatch::new(line_number, vec![(start, end, pattern)]))
             }
         }
 
@@ -828,15 +1891,17 @@ mod tests {
         let printer = DummyPrinter::default();
 
         let mut config = Config::new(1, 2);
+        config.pattern(pat);
         f(&mut config);
 
-        let found = grep(&printer, pat, Some(paths), config).unwrap();
+        let encoding = config.encoding;
+        let found = grep(&printer, Some(paths), config).unwrap();
         assert!(found, "file={}", file);
 
         let mut files = printer.0.into_inner().unwrap();
         assert_eq!(files.len(), 1, "file={}", file);
 
-        let expected = read_ripgrep_expected(file);
+        let expected = read_ripgrep_expected(file, encoding);
         assert_eq!(files.pop().unwrap(), expected, "file={}", file);
     }
 
@@ -880,6 +1945,575 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_multi_patterns() {
+        test_ripgrep_config("multi_patterns.txt", r"fo+", |c| {
+            c.pattern(r"ba+r").pattern(r"b.z");
+        });
+    }
+
+    #[test]
+    fn test_multi_patterns_pcre2() {
+        test_ripgrep_config("multi_patterns.txt", r"fo+", |c| {
+            c.pcre2(true).pattern("bar").pattern(r"(?<=qux )baz");
+        });
+    }
+
+    #[test]
+    fn test_multi_patterns_fixed_strings() {
+        test_ripgrep_config("multi_patterns.txt", "foo", |c| {
+            c.fixed_strings(true).pattern("bar").pattern("baz");
+            // Many literals are matched with Aho-Corasick
+            for i in 0..50 {
+                c.pattern(format!("does not match {}", i));
+            }
+        });
+    }
+
+    #[test]
+    fn test_pattern_file() {
+        test_ripgrep_config("multi_patterns.txt", "foo", |c| {
+            let path = Path::new("testdata").join("ripgrep").join("patterns");
+            c.pattern_file(&path).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_alternation_patterns() {
+        test_ripgrep_config("multi_patterns.txt", r"fo+|bar|b[az]z", |_| {});
+    }
+
+    #[test]
+    fn test_split_alternation() {
+        let tests = [
+            ("foo", vec!["foo"]),
+            ("foo|bar", vec!["foo", "bar"]),
+            ("foo|ba(r|z)", vec!["foo", "ba(r|z)"]),
+            (r"a\|b|c", vec![r"a\|b", "c"]),
+            ("[|]|x", vec!["[|]", "x"]),
+            ("[]|]|x", vec!["[]|]", "x"]),
+            ("[^]|]|x", vec!["[^]|]", "x"]),
+            ("[[:alpha:]|]|x", vec!["[[:alpha:]|]", "x"]),
+            ("foo||", vec!["foo", "", ""]),
+        ];
+        for (pat, want) in tests.iter() {
+            assert_eq!(&split_alternation(pat), want, "pattern={:?}", pat);
+        }
+    }
+
+    #[test]
+    fn test_pattern_branches() {
+        let mut config = Config::new(1, 2);
+        config.pattern("foo|bar");
+        assert_eq!(config.pattern_branches(true), vec![["foo"], ["bar"]]);
+        assert!(config.pattern_branches(false).is_empty());
+        config.invert_match(true);
+        assert!(config.pattern_branches(true).is_empty());
+
+        let mut config = Config::new(1, 2);
+        config.pattern("foo|bar").fixed_strings(true);
+        assert!(config.pattern_branches(true).is_empty());
+
+        let mut config = Config::new(1, 2);
+        config.pattern("foo").pattern("bar|baz");
+        assert_eq!(config.pattern_branches(true), vec![["foo"], ["bar|baz"]]);
+
+        let mut config = Config::new(1, 2);
+        let pat = (0..=MAX_PATTERN_BRANCHES)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("|");
+        config.pattern(&pat);
+        assert!(config.pattern_branches(true).is_empty());
+
+        // Patterns given with --and follow the main patterns
+        config.and_pattern("foo");
+        assert_eq!(config.pattern_branches(true), vec![[pat], ["foo".into()]]);
+
+        let mut config = Config::new(1, 2);
+        config.pattern("foo|bar").and_pattern("baz|qux");
+        assert_eq!(
+            config.pattern_branches(true),
+            vec![["foo"], ["bar"], ["baz|qux"]],
+        );
+    }
+
+    #[test]
+    fn test_and_patterns() {
+        test_ripgrep_config("and_patterns.txt", r"lock\(\)", |c| {
+            c.and_pattern("await");
+        });
+    }
+
+    #[test]
+    fn test_and_patterns_near() {
+        test_ripgrep_config("and_patterns_near.txt", r"lock\(\)", |c| {
+            c.and_pattern("await").near(1);
+        });
+    }
+
+    #[test]
+    fn test_and_patterns_not_found() {
+        let path = Path::new("testdata")
+            .join("ripgrep")
+            .join("and_patterns.txt");
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(1, 2);
+        config.pattern(r"lock\(\)").and_pattern("does not exist");
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(!found);
+        assert!(printer.0.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_filter_pattern_groups() {
+        fn lmat(line_number: u64, patterns: &[usize]) -> GrepMatch {
+            GrepMatch {
+                path: PathBuf::from("test.txt"),
+                line_number,
+                ranges: patterns.iter().map(|p| (0, 1, *p)).collect(),
+                replaced: None,
+            }
+        }
+        // Patterns 0 and 1 are main patterns (group 0). Patterns 2 and 3 are groups 1 and 2
+        let tests = [
+            // (patterns of each line, number of groups, near, kept line numbers)
+            (vec![&[0][..], &[2]], 2, None, vec![1, 2]),
+            (vec![&[0][..], &[1]], 2, None, vec![]),
+            (vec![&[1][..], &[2]], 3, None, vec![]),
+            (vec![&[0, 2][..]], 2, Some(0), vec![1]),
+            (vec![&[0][..], &[], &[2]], 2, Some(1), vec![]),
+            (vec![&[0][..], &[], &[2]], 2, Some(2), vec![1, 3]),
+            (vec![&[0][..], &[2], &[], &[1]], 2, Some(1), vec![1, 2]),
+            // Line 4 is removed first, then line 3 loses its group 1 line nearby
+            (vec![&[0, 2][..], &[3], &[0], &[2]], 3, Some(1), vec![1, 2]),
+        ];
+        for (lines, num_groups, near, want) in tests {
+            let matches = lines
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| !p.is_empty())
+                .map(|(i, p)| lmat(i as u64 + 1, p))
+                .collect();
+            let got: Vec<_> =
+                filter_pattern_groups(matches, num_groups, near, |p| (p + 1).saturating_sub(2))
+                    .into_iter()
+                    .map(|m| m.line_number)
+                    .collect();
+            assert_eq!(got, want, "lines={:?} near={:?}", lines, near);
+        }
+    }
+
+    #[test]
+    fn test_search_zip() {
+        let dir = Path::new("testdata").join("decompress");
+        let path = dir.join("access.log.gz");
+        if grep_cli::DecompressionMatcher::new()
+            .command(&path)
+            .is_none()
+        {
+            return; // gzip command is not installed
+        }
+
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("api").search_zip(true);
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(found);
+
+        let files = printer.0.into_inner().unwrap();
+        assert_eq!(files.len(), 1);
+        let lnums: Vec<_> = files[0]
+            .line_matches
+            .iter()
+            .map(|m| m.line_number)
+            .collect();
+        assert_eq!(lnums, [3, 4]);
+        let want = fs::read(dir.join("access.log")).unwrap();
+        assert_eq!(&*files[0].contents, want.as_slice());
+
+        // Compressed file is not searched without decompression
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("api");
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(!found);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pre_command() {
+        let dir = Path::new("testdata").join("pre");
+        let command = dir.join("upper.sh");
+        let path = dir.join("notes.txt");
+
+        // Converted text is searched and printed
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("PRINTER").pre(&command);
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(found);
+
+        let files = printer.0.into_inner().unwrap();
+        assert_eq!(files.len(), 1);
+        let lnums: Vec<_> = files[0]
+            .line_matches
+            .iter()
+            .map(|m| m.line_number)
+            .collect();
+        assert_eq!(lnums, [4]);
+        let want = fs::read_to_string(&path).unwrap().to_uppercase();
+        assert_eq!(&*files[0].contents, want.as_bytes());
+
+        // The file is not converted when it does not match the globs
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config
+            .pattern("PRINTER")
+            .pre(&command)
+            .pre_globs(iter::once("*.pdf"));
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(!found);
+
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config
+            .pattern("PRINTER")
+            .pre(&command)
+            .pre_globs(["*.pdf", "*.txt"].iter().copied());
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(found);
+    }
+
+    #[test]
+    fn test_search_archive() {
+        let dir = Path::new("testdata").join("archive");
+        let path = dir.join("app.jar");
+
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("password").search_archive(true);
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(found);
+
+        let mut files = printer.0.into_inner().unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let got: Vec<_> = files
+            .iter()
+            .map(|f| {
+                let lnums: Vec<_> = f.line_matches.iter().map(|m| m.line_number).collect();
+                (f.path.clone(), lnums)
+            })
+            .collect();
+        let want = vec![
+            (dir.join("app.jar!/META-INF/application.yml"), vec![7]),
+            (dir.join("app.jar!/src/main.rs"), vec![2, 3]),
+        ];
+        assert_eq!(got, want);
+        let yml = fs::read(dir.join("application.yml")).unwrap();
+        assert_eq!(&*files[0].contents, yml.as_slice());
+
+        // Archive is not searched without the option
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("password");
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(!found);
+    }
+
+    #[test]
+    fn test_search_contents() {
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("image");
+        let rg = Ripgrep::with_regex(config, &printer).unwrap();
+        let contents = b"spec:\n  containers:\n    - name: web\n      image: nginx:1.21\n";
+        let path = PathBuf::from("<stdin>");
+        let found = rg.search_contents(path.clone(), contents.to_vec()).unwrap();
+        assert!(found);
+
+        let found = rg
+            .search_contents(path.clone(), b"not matched\n".to_vec())
+            .unwrap();
+        assert!(!found);
+
+        let files = printer.0.into_inner().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, path);
+        assert_eq!(&*files[0].contents, contents.as_slice());
+        assert_eq!(&*files[0].chunks, &[(4, 4)]);
+        let lnums: Vec<_> = files[0]
+            .line_matches
+            .iter()
+            .map(|m| m.line_number)
+            .collect();
+        assert_eq!(lnums, [4]);
+    }
+
+    #[test]
+    fn test_search_keeps_contents() {
+        let dir = Path::new("testdata").join("archive");
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("password").search_archive(true);
+        let rg = Ripgrep::with_regex(config, &printer).unwrap();
+
+        // Contents read by the searcher are handed to the printer
+        let path = dir.join("main.rs");
+        let found = rg.search(path.clone()).unwrap().unwrap();
+        let want = vec![(path, fs::read(dir.join("main.rs")).unwrap())];
+        assert_eq!(found.loaded, want);
+
+        // Only matched members of the archive are kept
+        let path = dir.join("app.jar");
+        let found = rg.search(path.clone()).unwrap().unwrap();
+        let mut loaded = found.loaded;
+        loaded.sort();
+        let want = vec![
+            (
+                archive::member_path(&path, Path::new("META-INF/application.yml")),
+                fs::read(dir.join("application.yml")).unwrap(),
+            ),
+            (
+                archive::member_path(&path, Path::new("src/main.rs")),
+                fs::read(dir.join("main.rs")).unwrap(),
+            ),
+        ];
+        assert_eq!(loaded, want);
+
+        // Contents of memory-mapped files are not kept
+        let mut config = Config::new(0, 0);
+        config.pattern("password").mmap(true);
+        let rg = Ripgrep::with_regex(config, &printer).unwrap();
+        let found = rg.search(dir.join("main.rs")).unwrap().unwrap();
+        assert!(found.loaded.is_empty());
+        assert_eq!(found.matches.len(), 2);
+
+        // Whole contents are kept even if the searcher stops in the middle of the file
+        let mut config = Config::new(0, 0);
+        config.pattern("fn main").max_count(1);
+        let rg = Ripgrep::with_regex(config, &printer).unwrap();
+        let path = dir.join("main.rs");
+        let found = rg.search(path.clone()).unwrap().unwrap();
+        assert_eq!(found.matches.len(), 1);
+        let want = vec![(path, fs::read(dir.join("main.rs")).unwrap())];
+        assert_eq!(found.loaded, want);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let contents = b"foo\nbar\nbaz\n";
+        let mut buf = [0; 4];
+
+        let mut recorder = Recorder::new(&contents[..], 100);
+        io::Read::read_exact(&mut recorder, &mut buf).unwrap();
+        assert_eq!(recorder.into_contents().unwrap().unwrap(), contents);
+
+        // Kept bytes are dropped when they exceed the limit
+        let mut recorder = Recorder::new(&contents[..], 8);
+        io::Read::read_exact(&mut recorder, &mut buf).unwrap();
+        assert_eq!(recorder.kept.as_deref(), Some(&b"foo\n"[..]));
+        assert_eq!(recorder.into_contents().unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_stdin() {
+        let paths = iter::once(Path::new("-"));
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(0, 0);
+        config.pattern("foo").replace("bar").write(true);
+        let err = grep(&printer, Some(paths), config).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(msg.contains("stdin"), "message={:?}", msg);
+    }
+
+    #[test]
+    fn test_no_pattern() {
+        let path = Path::new("testdata")
+            .join("ripgrep")
+            .join("multi_patterns.txt");
+        let paths = iter::once(path.as_path());
+        let printer = DummyPrinter::default();
+        let found = grep(&printer, Some(paths), Config::new(1, 2)).unwrap();
+        assert!(!found);
+        assert!(printer.0.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_utf16_bom() {
+        test_ripgrep_config("utf16le_bom.txt", r"this is test", |_| {});
+    }
+
+    #[test]
+    fn test_shift_jis() {
+        test_ripgrep_config("shift_jis.txt", r"this is test", |c| {
+            c.encoding("shift_jis".parse().unwrap());
+        });
+    }
+
+    fn replaced_lines<M: Matcher + Sync>(
+        rg: Ripgrep<'_, M, &DummyPrinter>,
+        file: &str,
+    ) -> Vec<(u64, Option<Vec<Replacement>>)> {
+        let path = Path::new("testdata").join("ripgrep").join(file);
+        rg.search(path)
+            .unwrap()
+            .unwrap()
+            .matches
+            .into_iter()
+            .map(|m| (m.line_number, m.replaced))
+            .collect()
+    }
+
+    fn replacement(text: &str, ranges: Vec<(usize, usize, usize)>) -> Replacement {
+        Replacement {
+            text: text.as_bytes().to_vec(),
+            ranges,
+        }
+    }
+
+    #[test]
+    fn test_replace_captures() {
+        let printer = DummyPrinter::default();
+        let tests = [
+            (r"(\w+) is (\w+)", "$2 is $1"),
+            (r"(?P<x>\w+) is (?P<y>\w+)", "${y} is ${x}"),
+        ];
+        for (pat, template) in tests.iter().copied() {
+            let mut config = Config::new(1, 2);
+            config.replace(template);
+            config.pattern(pat);
+            let rg = Ripgrep::with_regex(config, &printer).unwrap();
+            let want = vec![(5, Some(vec![replacement("test is this", vec![(0, 12, 0)])]))];
+            assert_eq!(replaced_lines(rg, "pcre2.txt"), want, "pat={:?}", pat);
+        }
+    }
+
+    #[test]
+    fn test_replace_pcre2() {
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(1, 2);
+        config.pcre2(true).replace("[$1]");
+        config.pattern(r"(?<=is )(t)est");
+        let rg = Ripgrep::with_pcre2(config, &printer).unwrap();
+        let want = vec![(5, Some(vec![replacement("this is [t]", vec![(8, 11, 0)])]))];
+        assert_eq!(replaced_lines(rg, "pcre2.txt"), want);
+    }
+
+    #[test]
+    fn test_replace_multiline() {
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(1, 2);
+        config.multiline(true).replace("that\nwas a");
+        config.pattern(r"this\nis the\ntest");
+        let rg = Ripgrep::with_regex(config, &printer).unwrap();
+        let got = replaced_lines(rg, "multiline.txt");
+        // Replaced lines are put to the last line of each match
+        let want = [
+            (5, Some(vec![])),
+            (6, Some(vec![])),
+            (
+                7,
+                Some(vec![
+                    replacement("aaa that", vec![(4, 8, 0)]),
+                    replacement("was a string bbb", vec![(0, 5, 0)]),
+                ]),
+            ),
+        ];
+        assert_eq!(&got[..3], &want[..]);
+    }
+
+    #[test]
+    fn test_replace_invert_match() {
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(1, 2);
+        config.invert_match(true).replace("foo");
+        config.pattern(r"this is test");
+        let rg = Ripgrep::with_regex(config, &printer).unwrap();
+        let got = replaced_lines(rg, "pcre2.txt");
+        assert!(got.iter().all(|(_, r)| r.is_none()), "{:?}", got);
+    }
+
+    #[test]
+    fn test_replace_write() {
+        let dir = env::temp_dir().join(format!("hgrep-replace-write-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.txt");
+        fs::write(&path, "foo_bar\nfoo\nbar foo_baz\n").unwrap();
+
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(1, 2);
+        config.pattern(r"foo_(\w+)").replace("${1}_qux").write(true);
+        let paths = iter::once(path.as_path());
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(found);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "bar_qux\nfoo\nbar baz_qux\n"
+        );
+
+        // Printed file has contents before replacing
+        let files = printer.0.into_inner().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(&*files[0].contents, b"foo_bar\nfoo\nbar foo_baz\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replace_write_and_patterns() {
+        let dir = env::temp_dir().join(format!("hgrep-replace-and-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.rs");
+        let contents = "let y = unsafe { transmute(x) };\nlet z = unsafe { f(x) };\n";
+        fs::write(&path, contents).unwrap();
+
+        let printer = DummyPrinter::default();
+        let mut config = Config::new(1, 2);
+        config
+            .pattern("unsafe")
+            .and_pattern("transmute")
+            .replace("SAFE")
+            .write(true);
+        let paths = iter::once(path.as_path());
+        let found = grep(&printer, Some(paths), config).unwrap();
+        assert!(found);
+        // Regions matched to the --and pattern are not replaced
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "let y = SAFE { transmute(x) };\nlet z = SAFE { f(x) };\n",
+        );
+
+        let files = printer.0.into_inner().unwrap();
+        assert_eq!(files.len(), 1);
+        let replaced = files[0].line_matches[0].replaced.as_ref().unwrap();
+        assert_eq!(
+            replaced,
+            &vec![replacement(
+                "let y = SAFE { transmute(x) };",
+                vec![(8, 12, 0)]
+            )],
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_line_regions_pattern() {
+        let ranges = [(1, 3, 2), (4, 7, 1)];
+        let mut r = LineRegions::new(&ranges);
+        assert_eq!(r.line_ranges(5), vec![(1, 3, 2), (4, 5, 1)]);
+        assert_eq!(r.line_ranges(5), vec![(0, 2, 1)]);
+    }
+
     macro_rules! line_regions_tests {
         {$(
             $name:ident(
@@ -891,17 +2525,19 @@ mod tests {
             $(
                 #[test]
                 fn $name() {
-                    let ranges = &$ranges;
+                    let ranges: &[(usize, usize)] = &$ranges;
+                    let ranges: Vec<_> = ranges.iter().map(|&(s, e)| (s, e, 0)).collect();
                     let line_lens = &$line_lens;
-                    let expected = &[
+                    let expected: &[&[(usize, usize)]] = &[
                         $(
                             &$expected[..],
                         )*
                     ];
 
-                    let mut r = LineRegions::new(ranges);
+                    let mut r = LineRegions::new(&ranges);
                     for (idx, len) in line_lens.iter().copied().enumerate() {
-                        assert_eq!(&r.line_ranges(len), expected[idx], "index={}", idx);
+                        let got: Vec<_> = r.line_ranges(len).into_iter().map(|(s, e, _)| (s, e)).collect();
+                        assert_eq!(&got, expected[idx], "index={}", idx);
                     }
                 }
             )+
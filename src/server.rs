@@ -0,0 +1,578 @@
+use crate::chunk::File;
+use crate::printer::{Printer, PrinterOptions};
+use crate::ripgrep::{self, Config, Summary};
+use crate::syntect::{LockableWrite, SyntectAssets, SyntectPrinter};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+// Server for editor integrations started with `--server`. It speaks JSON-RPC 2.0 where each message is one line of
+// JSON. Requests are read from the input and responses are written to the output. Assets for syntax highlighting are
+// loaded only once on starting the server.
+//
+// Methods:
+// - `search`: Search files and send `file` notification for each file with matches. The response is sent after
+//   searching all files. The request must have an ID which is unique among running searches
+// - `cancel`: Stop the running search request with the `id` parameter
+// - `shutdown`: Stop reading requests and respond after all running searches finished
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SEARCH_FAILED: i64 = -32000;
+
+// Same as the default values of `--min-context` and `--max-context`
+const DEFAULT_MIN_CONTEXT: u64 = 3;
+const DEFAULT_MAX_CONTEXT: u64 = 6;
+
+// Number of worker threads to run searches. More search requests wait in the queue until some worker is available.
+// Each search also searches files in parallel so a few workers are enough
+const MAX_CONCURRENT_SEARCHES: usize = 4;
+
+type SearchJob = (Value, SearchParams, Arc<AtomicBool>);
+
+#[derive(Deserialize)]
+struct Request {
+    // Notification does not have ID
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    // Code snippets rendered by the syntect printer with ANSI escape sequences
+    #[default]
+    Rendered,
+    // Structured chunks of lines with byte offsets of matched regions
+    Chunks,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SearchParams {
+    patterns: Vec<String>,
+    // The current directory of the server is searched when no path is given
+    paths: Vec<PathBuf>,
+    format: Format,
+    min_context: Option<u64>,
+    max_context: Option<u64>,
+    case_insensitive: bool,
+    smart_case: bool,
+    fixed_strings: bool,
+    word_regexp: bool,
+    hidden: bool,
+    no_ignore: bool,
+    globs: Vec<String>,
+    multiline: bool,
+    invert_match: bool,
+    max_count: Option<u64>,
+    max_total: Option<u64>,
+    max_files: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct CancelParams {
+    id: Value,
+}
+
+// The syntect printer only renders files in server mode. Rendered outputs are sent as JSON messages
+struct NoOutput;
+
+impl<'a> LockableWrite<'a> for NoOutput {
+    type Locked = io::Sink;
+    fn lock(&'a self) -> Self::Locked {
+        io::sink()
+    }
+}
+
+pub struct Server<'main, W: Write> {
+    printer: SyntectPrinter<'main, NoOutput>,
+    output: Mutex<W>,
+    // Cancellation flags of running searches by their request IDs
+    running: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl<'main, W: Write + Send> Server<'main, W> {
+    pub fn new(opts: PrinterOptions<'main>, output: W) -> Result<Self> {
+        let assets = SyntectAssets::load(opts.theme)?;
+        Ok(Self::with_assets(assets, opts, output))
+    }
+
+    pub fn with_assets(assets: SyntectAssets, opts: PrinterOptions<'main>, output: W) -> Self {
+        let mut printer = SyntectPrinter::with_assets(assets, NoOutput, opts);
+        // Clients such as editors search the same files repeatedly
+        printer.cache_checkpoints(true);
+        Self {
+            printer,
+            output: Mutex::new(output),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Serve requests until the input is closed or `shutdown` request is received. Searches run on worker threads so
+    // that requests can be cancelled while searching. Running and queued searches are waited before returning
+    pub fn serve(&self, input: impl BufRead) -> Result<()> {
+        let (tx, rx) = mpsc::channel::<SearchJob>();
+        let rx = Mutex::new(rx);
+        let shutdown = thread::scope(|scope| {
+            for _ in 0..MAX_CONCURRENT_SEARCHES {
+                scope.spawn(|| loop {
+                    // The lock is released before running the search so that other workers can receive jobs
+                    let job = rx.lock().unwrap().recv();
+                    match job {
+                        Ok((id, params, cancel)) => self.run_search(id, params, cancel),
+                        Err(_) => return, // The sender was dropped since no request will be read
+                    }
+                });
+            }
+
+            // The sender is dropped when this closure returns so that workers stop after finishing all jobs
+            let tx = tx;
+            for line in input.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let req: Request = match serde_json::from_str(&line) {
+                    Ok(req) => req,
+                    Err(err) => {
+                        self.send_error(&Value::Null, PARSE_ERROR, err.to_string())?;
+                        continue;
+                    }
+                };
+                let id = req.id.unwrap_or(Value::Null);
+                match req.method.as_str() {
+                    "search" => {
+                        if id.is_null() {
+                            let msg = "Search request must have ID".to_string();
+                            self.send_error(&id, INVALID_REQUEST, msg)?;
+                            continue;
+                        }
+                        let params: SearchParams = match serde_json::from_value(req.params) {
+                            Ok(params) => params,
+                            Err(err) => {
+                                self.send_error(&id, INVALID_PARAMS, err.to_string())?;
+                                continue;
+                            }
+                        };
+                        let cancel = Arc::new(AtomicBool::new(false));
+                        // Cancellation flag of the running search must not be overwritten
+                        let duplicate = match self.running.lock().unwrap().entry(id.to_string()) {
+                            Entry::Occupied(_) => true,
+                            Entry::Vacant(entry) => {
+                                entry.insert(cancel.clone());
+                                false
+                            }
+                        };
+                        if duplicate {
+                            let msg = format!("Search request with ID {} is already running", id);
+                            self.send_error(&id, INVALID_REQUEST, msg)?;
+                            continue;
+                        }
+                        // Receivers are alive until this closure returns
+                        tx.send((id, params, cancel)).unwrap();
+                    }
+                    "cancel" => {
+                        let params: CancelParams = match serde_json::from_value(req.params) {
+                            Ok(params) => params,
+                            Err(err) => {
+                                self.send_error(&id, INVALID_PARAMS, err.to_string())?;
+                                continue;
+                            }
+                        };
+                        let running = self.running.lock().unwrap();
+                        let flag = running.get(&params.id.to_string());
+                        if let Some(flag) = flag {
+                            flag.store(true, Ordering::Relaxed);
+                        }
+                        if !id.is_null() {
+                            self.send_result(&id, json!(flag.is_some()))?;
+                        }
+                    }
+                    "shutdown" => return Ok(Some(id)),
+                    method => {
+                        let msg = format!("Unknown method {:?}", method);
+                        self.send_error(&id, METHOD_NOT_FOUND, msg)?;
+                    }
+                }
+            }
+            Ok::<_, anyhow::Error>(None)
+        })?;
+
+        // Respond to `shutdown` after all running searches finished
+        if let Some(id) = shutdown {
+            self.send_result(&id, Value::Null)?;
+        }
+        Ok(())
+    }
+
+    fn run_search(&self, id: Value, params: SearchParams, cancel: Arc<AtomicBool>) {
+        let result = self.search(&id, &params, cancel.clone());
+        self.running.lock().unwrap().remove(&id.to_string());
+        let sent = match result {
+            Ok(summary) => {
+                let result = json!({
+                    "found": summary.found,
+                    "truncated": summary.truncated,
+                    "cancelled": cancel.load(Ordering::Relaxed),
+                });
+                self.send_result(&id, result)
+            }
+            Err(err) => self.send_error(&id, SEARCH_FAILED, format!("{:#}", err)),
+        };
+        let _ = sent; // Failing to send the response means that the client is already gone
+    }
+
+    fn search(
+        &self,
+        id: &Value,
+        params: &SearchParams,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<Summary> {
+        if params.patterns.is_empty() {
+            anyhow::bail!("No pattern is given to search request");
+        }
+        if params.paths.iter().any(|p| p == Path::new("-")) {
+            anyhow::bail!("Stdin cannot be searched in server mode");
+        }
+
+        let min = params.min_context.unwrap_or(DEFAULT_MIN_CONTEXT);
+        let max = cmp::max(min, params.max_context.unwrap_or(DEFAULT_MAX_CONTEXT));
+        let mut config = Config::new(min, max);
+        for pattern in params.patterns.iter() {
+            config.pattern(pattern.as_str());
+        }
+        config
+            .case_insensitive(params.case_insensitive)
+            .smart_case(params.smart_case)
+            .fixed_strings(params.fixed_strings)
+            .word_regexp(params.word_regexp)
+            .hidden(params.hidden)
+            .no_ignore(params.no_ignore)
+            .globs(params.globs.iter().map(String::as_str))
+            .multiline(params.multiline)
+            .invert_match(params.invert_match)
+            .cancel(cancel);
+        if let Some(num) = params.max_count {
+            config.max_count(num);
+        }
+        if let Some(num) = params.max_total {
+            config.max_total(num);
+        }
+        if let Some(num) = params.max_files {
+            config.max_files(num);
+        }
+
+        let printer = ServerPrinter {
+            id,
+            server: self,
+            format: params.format,
+        };
+        let paths = if params.paths.is_empty() {
+            None
+        } else {
+            Some(params.paths.iter().map(PathBuf::as_path))
+        };
+        ripgrep::grep_with_summary(printer, paths, config)
+    }
+
+    fn send(&self, msg: Value) -> io::Result<()> {
+        let mut out = self.output.lock().unwrap();
+        serde_json::to_writer(&mut *out, &msg)?;
+        out.write_all(b"\n")?;
+        out.flush()
+    }
+
+    fn send_result(&self, id: &Value, result: Value) -> io::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    fn send_error(&self, id: &Value, code: i64, message: String) -> io::Result<()> {
+        let error = json!({ "code": code, "message": message });
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "error": error }))
+    }
+}
+
+// Lines in chunks of the file. Ranges of matched regions are byte offsets in the line
+fn chunks_to_json(file: &File) -> Value {
+    let lines: Vec<&[u8]> = file.contents.split(|b| *b == b'\n').collect();
+    let chunks = file.chunks.iter().map(|&(start, end)| {
+        let lines = (start..=end).filter_map(|lnum| {
+            let line = lines.get(lnum as usize - 1)?;
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let matched = file.line_matches.iter().find(|m| m.line_number == lnum);
+            let ranges = matched
+                .iter()
+                .flat_map(|m| m.ranges.iter().map(|(s, e, _)| [*s, *e]))
+                .collect::<Vec<_>>();
+            Some(json!({
+                "number": lnum,
+                "text": String::from_utf8_lossy(line),
+                "matched": matched.is_some(),
+                "ranges": ranges,
+            }))
+        });
+        json!({ "start": start, "end": end, "lines": lines.collect::<Vec<_>>() })
+    });
+    Value::Array(chunks.collect())
+}
+
+// Printer to send each file as `file` notification
+struct ServerPrinter<'a, 'main, W: Write> {
+    id: &'a Value,
+    server: &'a Server<'main, W>,
+    format: Format,
+}
+
+impl<'a, 'main, W: Write + Send> Printer for ServerPrinter<'a, 'main, W> {
+    fn print(&self, file: File) -> Result<()> {
+        let mut params = json!({ "id": self.id, "path": file.path.to_string_lossy() });
+        match self.format {
+            Format::Rendered => {
+                let rendered = self.server.printer.draw(&file)?;
+                params["rendered"] = String::from_utf8_lossy(&rendered).into();
+            }
+            Format::Chunks => params["chunks"] = chunks_to_json(&file),
+        }
+        let msg = json!({ "jsonrpc": "2.0", "method": "file", "params": params });
+        Ok(self.server.send(msg)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::TermColorSupport;
+    use pretty_assertions::assert_eq;
+
+    lazy_static::lazy_static! {
+        static ref ASSETS: SyntectAssets = SyntectAssets::load(None).unwrap();
+    }
+
+    fn serve(input: &str) -> Vec<Value> {
+        let opts = PrinterOptions {
+            color_support: TermColorSupport::True,
+            term_width: 80,
+            ..Default::default()
+        };
+        let server = Server::with_assets(ASSETS.clone(), opts, vec![]);
+        server.serve(input.as_bytes()).unwrap();
+        let output = server.output.into_inner().unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    fn search_request(id: u64, params: Value) -> String {
+        let req = json!({ "jsonrpc": "2.0", "id": id, "method": "search", "params": params });
+        format!("{}\n", req)
+    }
+
+    #[test]
+    fn test_search_chunks() {
+        let path = Path::new("testdata").join("archive").join("main.rs");
+        let params = json!({
+            "patterns": ["password"],
+            "paths": [path],
+            "format": "chunks",
+            "min_context": 0,
+            "max_context": 0,
+        });
+        let got = serve(&search_request(1, params));
+        assert_eq!(got.len(), 2, "messages={:?}", got);
+
+        let notification = &got[0];
+        assert_eq!(notification["method"], "file");
+        assert_eq!(notification["params"]["id"], 1);
+        assert_eq!(notification["params"]["path"], path.to_str().unwrap());
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let chunks = notification["params"]["chunks"].as_array().unwrap();
+        let lines: Vec<_> = chunks
+            .iter()
+            .flat_map(|c| c["lines"].as_array().unwrap().iter())
+            .map(|l| {
+                let lnum = l["number"].as_u64().unwrap();
+                assert_eq!(l["matched"], true);
+                let text = l["text"].as_str().unwrap();
+                assert_eq!(text, contents.lines().nth(lnum as usize - 1).unwrap());
+                let range = &l["ranges"][0];
+                let (s, e) = (range[0].as_u64().unwrap(), range[1].as_u64().unwrap());
+                (lnum, &text[s as usize..e as usize])
+            })
+            .collect();
+        assert_eq!(lines, [(2, "password"), (3, "password")]);
+
+        let want = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "found": true, "truncated": false, "cancelled": false },
+        });
+        assert_eq!(got[1], want);
+    }
+
+    #[test]
+    fn test_search_rendered() {
+        let path = Path::new("testdata")
+            .join("archive")
+            .join("application.yml");
+        let params = json!({ "patterns": ["password"], "paths": [path] });
+        let got = serve(&search_request(1, params));
+        assert_eq!(got.len(), 2, "messages={:?}", got);
+        let rendered = got[0]["params"]["rendered"].as_str().unwrap();
+        assert!(rendered.contains("password"), "rendered={:?}", rendered);
+        assert!(rendered.contains("\x1b["), "rendered={:?}", rendered);
+        assert_eq!(got[1]["result"]["found"], true);
+
+        // Response is sent even if nothing is found
+        let params = json!({ "patterns": ["this does not match!!!"], "paths": [path] });
+        let got = serve(&search_request(2, params));
+        assert_eq!(got.len(), 1, "messages={:?}", got);
+        assert_eq!(got[0]["id"], 2);
+        assert_eq!(got[0]["result"]["found"], false);
+    }
+
+    #[test]
+    fn test_search_cancelled() {
+        let server = Server::with_assets(ASSETS.clone(), PrinterOptions::default(), vec![]);
+        let path = Path::new("testdata").join("archive").join("main.rs");
+        let params = SearchParams {
+            patterns: vec!["password".to_string()],
+            paths: vec![path],
+            ..Default::default()
+        };
+        let summary = server
+            .search(&json!(1), &params, Arc::new(AtomicBool::new(true)))
+            .unwrap();
+        assert!(!summary.found);
+        assert!(server.output.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let input = [
+            "this is not JSON".to_string(),
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "unknown" }).to_string(),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "search", "params": { "pattern": "foo" } })
+                .to_string(),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "cancel", "params": { "id": 42 } })
+                .to_string(),
+        ]
+        .join("\n");
+        let got = serve(&input);
+        assert_eq!(got.len(), 4, "messages={:?}", got);
+        assert_eq!(got[0]["id"], Value::Null);
+        assert_eq!(got[0]["error"]["code"], PARSE_ERROR);
+        assert_eq!(got[1]["id"], 1);
+        assert_eq!(got[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(got[2]["id"], 2);
+        assert_eq!(got[2]["error"]["code"], INVALID_PARAMS);
+        // Unknown search is not cancelled
+        assert_eq!(got[3]["id"], 3);
+        assert_eq!(got[3]["result"], false);
+
+        for (params, msg) in [
+            (json!({ "paths": ["."] }), "No pattern"),
+            (json!({ "patterns": ["foo"], "paths": ["-"] }), "Stdin"),
+        ] {
+            let got = serve(&search_request(1, params));
+            assert_eq!(got.len(), 1, "messages={:?}", got);
+            assert_eq!(got[0]["error"]["code"], SEARCH_FAILED);
+            let message = got[0]["error"]["message"].as_str().unwrap();
+            assert!(message.contains(msg), "message={:?}", message);
+        }
+    }
+
+    #[test]
+    fn test_search_without_id() {
+        let params = json!({ "patterns": ["foo"], "paths": ["."] });
+        for req in [
+            json!({ "jsonrpc": "2.0", "method": "search", "params": params }),
+            json!({ "jsonrpc": "2.0", "id": null, "method": "search", "params": params }),
+        ] {
+            let got = serve(&req.to_string());
+            assert_eq!(got.len(), 1, "messages={:?}", got);
+            assert_eq!(got[0]["id"], Value::Null);
+            assert_eq!(got[0]["error"]["code"], INVALID_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_search_duplicate_id() {
+        let server = Server::with_assets(ASSETS.clone(), PrinterOptions::default(), vec![]);
+        let running = Arc::new(AtomicBool::new(false));
+        server
+            .running
+            .lock()
+            .unwrap()
+            .insert("1".to_string(), running.clone());
+        let path = Path::new("testdata").join("archive").join("main.rs");
+        let params = json!({ "patterns": ["password"], "paths": [path] });
+        let input = format!(
+            "{}{}",
+            search_request(1, params.clone()),
+            search_request(2, params)
+        );
+        server.serve(input.as_bytes()).unwrap();
+
+        let output = server.output.into_inner().unwrap();
+        let got: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let errors: Vec<_> = got.iter().filter(|m| m["id"] == 1).collect();
+        assert_eq!(errors.len(), 1, "messages={:?}", got);
+        assert_eq!(errors[0]["error"]["code"], INVALID_REQUEST);
+        // The flag of the running search is not overwritten
+        let flag = server.running.into_inner().unwrap().remove("1").unwrap();
+        assert!(Arc::ptr_eq(&flag, &running));
+        // Other search is not affected
+        let results: Vec<_> = got.iter().filter(|m| m["id"] == 2).collect();
+        assert_eq!(results.len(), 1, "messages={:?}", got);
+        assert_eq!(results[0]["result"]["found"], true);
+    }
+
+    #[test]
+    fn test_many_searches() {
+        let path = Path::new("testdata").join("archive").join("main.rs");
+        let params = json!({ "patterns": ["password"], "paths": [path], "format": "chunks" });
+        let count = MAX_CONCURRENT_SEARCHES as u64 * 3;
+        let input: String = (0..count)
+            .map(|id| search_request(id, params.clone()))
+            .collect();
+        let got = serve(&input);
+        let mut ids: Vec<_> = got
+            .iter()
+            .filter(|m| m.get("result").is_some())
+            .map(|m| m["id"].as_u64().unwrap())
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let input = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" }).to_string(),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "unknown" }).to_string(),
+        ]
+        .join("\n");
+        let got = serve(&input);
+        let want = vec![json!({ "jsonrpc": "2.0", "id": 1, "result": null })];
+        assert_eq!(got, want);
+    }
+}
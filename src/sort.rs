@@ -0,0 +1,377 @@
+#[cfg(any(feature = "ripgrep", feature = "syntect-printer"))]
+use crate::printer::{Printer, Rendered};
+use anyhow::{Error, Result};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortKey {
+    Path,
+    Modified,
+    Created,
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "path" => Ok(SortKey::Path),
+            "modified" => Ok(SortKey::Modified),
+            "created" => Ok(SortKey::Created),
+            _ => anyhow::bail!(
+                "Unknown sort key {:?}. It must be one of 'path', 'modified', 'created' or 'none'",
+                input,
+            ),
+        }
+    }
+}
+
+// How to sort files in output. Files are sorted in ascending order unless `reverse` is true
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sort {
+    pub key: SortKey,
+    pub reverse: bool,
+}
+
+impl Sort {
+    pub fn new(key: SortKey, reverse: bool) -> Self {
+        Self { key, reverse }
+    }
+
+    fn ordering(self, ord: Ordering) -> Ordering {
+        if self.reverse {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    pub fn compare_paths(self, a: &Path, b: &Path) -> Ordering {
+        self.ordering(a.cmp(b))
+    }
+
+    // Sort items by the files at their paths. The sort is stable so that the order of matches in the same file is kept.
+    // Files whose timestamps are not available come first. Files with the same timestamp are sorted by their paths
+    pub fn sort_by_file<T>(self, items: &mut [T], path: impl Fn(&T) -> &Path) {
+        let time = match self.key {
+            SortKey::Path => {
+                items.sort_by(|a, b| self.compare_paths(path(a), path(b)));
+                return;
+            }
+            SortKey::Modified => fs::Metadata::modified,
+            SortKey::Created => fs::Metadata::created,
+        };
+
+        let mut times: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+        for item in items.iter() {
+            let path = path(item);
+            if !times.contains_key(path) {
+                let t = fs::metadata(path).and_then(|m| time(&m)).ok();
+                times.insert(path.to_path_buf(), t);
+            }
+        }
+        items.sort_by(|a, b| {
+            let (a, b) = (path(a), path(b));
+            self.ordering(times[a].cmp(&times[b]).then_with(|| a.cmp(b)))
+        });
+    }
+}
+
+// Buffer to reorder items which are processed in parallel. Items pushed with their indices are returned in the order of
+// the indices as soon as all the preceding items are pushed
+pub struct Reorder<T> {
+    next: usize,
+    pending: BTreeMap<usize, T>,
+}
+
+impl<T> Default for Reorder<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Reorder<T> {
+    pub fn push(&mut self, index: usize, item: T) -> Vec<T> {
+        self.pending.insert(index, item);
+        let mut ready = vec![];
+        while let Some(item) = self.pending.remove(&self.next) {
+            ready.push(item);
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+// Render the outputs of items in parallel and write them in the order of the iterator. `render` returns rendered files
+// for each item. Returns whether some file was written
+#[cfg(any(feature = "ripgrep", feature = "syntect-printer"))]
+pub fn print_in_order<P, I, F>(printer: &P, items: I, render: F) -> Result<bool>
+where
+    P: Printer + Sync,
+    I: Iterator + Send,
+    I::Item: Send,
+    F: Fn(I::Item) -> Result<Vec<Rendered>> + Sync,
+{
+    // Enough to keep all threads busy while some file takes long time to render
+    let window = rayon::current_num_threads() * 4;
+    print_in_window(printer, items, window, render)
+}
+
+// Items are not rendered until all items more than `window` before them are written so that outputs of later items
+// don't pile up in memory while an early item is still being rendered
+#[cfg(any(feature = "ripgrep", feature = "syntect-printer"))]
+fn print_in_window<P, I, F>(printer: &P, items: I, window: usize, render: F) -> Result<bool>
+where
+    P: Printer + Sync,
+    I: Iterator + Send,
+    I::Item: Send,
+    F: Fn(I::Item) -> Result<Vec<Rendered>> + Sync,
+{
+    use rayon::iter::{ParallelBridge, ParallelIterator};
+    use std::mem;
+    use std::sync::{Condvar, Mutex};
+
+    struct Output {
+        reorder: Reorder<Vec<Rendered>>,
+        ready: Vec<Vec<Rendered>>, // Outputs which can be written in order
+        writing: bool,             // Some thread is writing the ready outputs
+        written: usize,            // Number of items whose outputs were written
+        failed: bool,              // Some item failed so the waiting threads should stop
+    }
+
+    let output = Mutex::new(Output {
+        reorder: Reorder::default(),
+        ready: vec![],
+        writing: false,
+        written: 0,
+        failed: false,
+    });
+    let written = Condvar::new();
+    let print = |index: usize, item: I::Item| -> Result<bool> {
+        // This never deadlocks since all items before this one were already taken by other threads
+        let state = output.lock().unwrap();
+        let state = written
+            .wait_while(state, |s| !s.failed && index >= s.written + window)
+            .unwrap();
+        if state.failed {
+            return Ok(false); // The error was already reported by the failed thread
+        }
+        drop(state);
+
+        let rendered = render(item)?;
+        let mut state = output.lock().unwrap();
+        let ready = state.reorder.push(index, rendered);
+        state.ready.extend(ready);
+        if state.writing {
+            return Ok(false); // The writing thread will also write the outputs pushed here
+        }
+
+        // Only one thread writes outputs at once so that they are not interleaved. The lock is not held while
+        // writing so that other threads are not blocked until a large file is written
+        state.writing = true;
+        let mut found = false;
+        loop {
+            let ready = mem::take(&mut state.ready);
+            if ready.is_empty() {
+                state.writing = false;
+                return Ok(found);
+            }
+            drop(state);
+            let len = ready.len();
+            for rendered in ready.into_iter().flatten() {
+                printer.write_rendered(rendered)?;
+                found = true;
+            }
+            state = output.lock().unwrap();
+            state.written += len;
+            written.notify_all();
+        }
+    };
+
+    items
+        .enumerate()
+        .par_bridge()
+        .map(|(index, item)| {
+            let result = print(index, item);
+            if result.is_err() {
+                // Items after the failed one are never written. Wake up the threads waiting for them
+                output.lock().unwrap().failed = true;
+                written.notify_all();
+            }
+            result
+        })
+        .try_reduce(|| false, |a, b| Ok(a || b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_sort_key() {
+        assert_eq!("path".parse::<SortKey>().unwrap(), SortKey::Path);
+        assert_eq!("modified".parse::<SortKey>().unwrap(), SortKey::Modified);
+        assert_eq!("created".parse::<SortKey>().unwrap(), SortKey::Created);
+        "size".parse::<SortKey>().unwrap_err();
+    }
+
+    #[test]
+    fn test_sort_by_file() {
+        let mut items = vec![
+            ("b.rs", 1),
+            ("a/c.rs", 1),
+            ("b.rs", 2),
+            ("a.rs", 1),
+            ("a/c.rs", 2),
+        ];
+        Sort::new(SortKey::Path, false).sort_by_file(&mut items, |i| Path::new(i.0));
+        // Paths are compared per component like walking directories
+        let want = [
+            ("a/c.rs", 1),
+            ("a/c.rs", 2),
+            ("a.rs", 1),
+            ("b.rs", 1),
+            ("b.rs", 2),
+        ];
+        assert_eq!(items, want);
+
+        // Order of items in the same file is kept on reverse sort
+        Sort::new(SortKey::Path, true).sort_by_file(&mut items, |i| Path::new(i.0));
+        let want = [
+            ("b.rs", 1),
+            ("b.rs", 2),
+            ("a.rs", 1),
+            ("a/c.rs", 1),
+            ("a/c.rs", 2),
+        ];
+        assert_eq!(items, want);
+    }
+
+    #[test]
+    fn test_sort_by_modified() {
+        let dir = env::temp_dir().join(format!("hgrep-sort-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("old.txt");
+        let new = dir.join("new.txt");
+        fs::File::create(&old).unwrap().write_all(b"old").unwrap();
+        let f = fs::File::create(&new).unwrap();
+        let modified = fs::metadata(&old).unwrap().modified().unwrap();
+        f.set_modified(modified + Duration::from_secs(10)).unwrap();
+        drop(f);
+        let missing = dir.join("missing.txt");
+
+        let mut items = vec![new.clone(), old.clone(), missing.clone(), new.clone()];
+        Sort::new(SortKey::Modified, false).sort_by_file(&mut items, |p| p.as_path());
+        assert_eq!(
+            items,
+            [missing.clone(), old.clone(), new.clone(), new.clone()]
+        );
+
+        Sort::new(SortKey::Modified, true).sort_by_file(&mut items, |p| p.as_path());
+        assert_eq!(items, [new.clone(), new, old, missing]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut reorder = Reorder::default();
+        assert!(reorder.push(2, 'c').is_empty());
+        assert!(reorder.push(1, 'b').is_empty());
+        assert_eq!(reorder.push(0, 'a'), ['a', 'b', 'c']);
+        assert_eq!(reorder.push(3, 'd'), ['d']);
+        assert!(reorder.push(5, 'f').is_empty());
+        assert_eq!(reorder.push(4, 'e'), ['e', 'f']);
+    }
+
+    #[cfg(any(feature = "ripgrep", feature = "syntect-printer"))]
+    mod print_in_window {
+        use super::*;
+        use crate::chunk::File;
+        use std::sync::Mutex;
+        use std::thread;
+
+        #[derive(Default)]
+        struct BytesPrinter(Mutex<Vec<u8>>);
+
+        impl Printer for BytesPrinter {
+            fn print(&self, _: File) -> Result<()> {
+                unreachable!()
+            }
+
+            fn write_rendered(&self, rendered: Rendered) -> Result<()> {
+                match rendered {
+                    Rendered::Bytes(buf) => self.0.lock().unwrap().extend(buf),
+                    Rendered::File(_) => unreachable!(),
+                }
+                Ok(())
+            }
+        }
+
+        // Run with multiple threads even on a machine with single CPU
+        fn in_pool<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(4)
+                .build()
+                .unwrap();
+            pool.install(f)
+        }
+
+        #[test]
+        fn test_first_item_is_slowest() {
+            let printer = BytesPrinter::default();
+            let window = 3;
+            let found = in_pool(|| {
+                print_in_window(&printer, 0..20u8, window, |i| {
+                    // Later items do not run ahead while the first item is being rendered
+                    let written = printer.0.lock().unwrap().len();
+                    assert!(
+                        (i as usize) < written + window,
+                        "i={} written={}",
+                        i,
+                        written
+                    );
+                    if i == 0 {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                    Ok(vec![Rendered::Bytes(vec![i])])
+                })
+            })
+            .unwrap();
+            assert!(found);
+            let written = printer.0.into_inner().unwrap();
+            assert_eq!(written, (0..20).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn test_stop_on_error() {
+            let printer = BytesPrinter::default();
+            let err = in_pool(|| {
+                print_in_window(&printer, 0..20u8, 2, |i| {
+                    if i == 3 {
+                        anyhow::bail!("error at {}", i);
+                    }
+                    Ok(vec![Rendered::Bytes(vec![i])])
+                })
+            })
+            .unwrap_err();
+            assert_eq!(format!("{}", err), "error at 3");
+            // Items after the failed one are never written
+            let written = printer.0.into_inner().unwrap();
+            assert!(written.len() <= 3, "written={:?}", written);
+            assert_eq!(written, (0..written.len() as u8).collect::<Vec<_>>());
+        }
+    }
+}
@@ -1,20 +1,37 @@
 use crate::chunk::{File, Line};
-use crate::printer::{Printer, PrinterOptions, TermColorSupport, TextWrapMode};
-use ansi_colours::ansi256_from_rgb;
+use crate::decompress;
+use crate::git::{LineBlames, LineChange, LineChanges};
+use crate::printer::{
+    CustomColors, Printer, PrinterOptions, RegionStyle, Rendered, TermColor, TermColorSupport,
+    TextWrapMode,
+};
+use ansi_colours::{ansi256_from_rgb, rgb_from_ansi256};
 use anyhow::Result;
 use flate2::read::ZlibDecoder;
 use memchr::{memchr_iter, Memchr};
+use once_cell::sync::OnceCell;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
 use std::ffi::OsStr;
 use std::fmt;
-use std::io::{self, Stdout, StdoutLock, Write};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Stdout, StdoutLock, Write};
+use std::iter::Peekable;
+use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::Chars;
 use syntect::highlighting::{
     Color, FontStyle, HighlightIterator, HighlightState, Highlighter, Style, Theme, ThemeSet,
 };
 use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 // Note for lifetimes:
@@ -24,12 +41,143 @@ use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 const SYNTAX_SET_BIN: &[u8] = include_bytes!("../assets/syntaxes.bin");
 const THEME_SET_BIN: &[u8] = include_bytes!("../assets/themes.bin");
 
+// Names of themes in THEME_SET_BIN. They are used to validate the theme name without loading the theme set
+const BAT_THEME_NAMES: &[&str] = &[
+    "1337",
+    "Carbonight",
+    "Coldark-Dark",
+    "Cyanide",
+    "Dracula",
+    "GitHub",
+    "Material-Theme",
+    "Monokai Extended",
+    "Monokai Extended Bright",
+    "Monokai Extended Light",
+    "Nord",
+    "OneHalfDark",
+    "OneHalfLight",
+    "Solarized (dark)",
+    "Solarized (light)",
+    "Sublime Snazzy",
+    "TwoDark",
+    "Visual Studio Dark+",
+    "ansi",
+    "ayu-dark",
+    "ayu-light",
+    "ayu-mirage",
+    "gruvbox-dark",
+    "gruvbox-light",
+    "predawn",
+    "zenburn",
+];
+
+// Names of themes in `ThemeSet::load_defaults()`
+const DEFAULT_THEME_NAMES: &[&str] = &[
+    "InspiredGitHub",
+    "Solarized (dark)",
+    "Solarized (light)",
+    "base16-eighties.dark",
+    "base16-mocha.dark",
+    "base16-ocean.dark",
+    "base16-ocean.light",
+];
+
 fn load_bat_themes() -> Result<ThemeSet> {
     Ok(bincode::deserialize_from(ZlibDecoder::new(THEME_SET_BIN))?)
 }
 
 fn load_syntax_set() -> Result<SyntaxSet> {
-    Ok(bincode::deserialize_from(ZlibDecoder::new(SYNTAX_SET_BIN))?)
+    load_syntax_set_with_cache(asset_cache_dir().as_deref())
+}
+
+// Directory to cache the decompressed syntax set. `HGREP_CACHE_PATH` environment variable is preferred. Empty value
+// disables the cache
+fn asset_cache_dir() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None; // Tests should not write files to the user's cache directory
+    }
+    if let Some(path) = env::var_os("HGREP_CACHE_PATH") {
+        return Some(PathBuf::from(path)).filter(|p| !p.as_os_str().is_empty());
+    }
+    dirs_next::cache_dir().map(|d| d.join("hgrep"))
+}
+
+// Version of the cache file format. Increment this when the way to serialize the syntax set is changed
+const SYNTAX_CACHE_VERSION: u32 = 1;
+
+// The cache file name contains hgrep's version, the format version, and the Adler-32 checksum of the embedded syntax
+// set so that a stale cache is never used after updating hgrep. The checksum is the last 4 bytes of the zlib stream
+fn syntax_set_cache_path(dir: &Path) -> PathBuf {
+    let checksum = match SYNTAX_SET_BIN {
+        [.., a, b, c, d] => u32::from_be_bytes([*a, *b, *c, *d]),
+        _ => 0,
+    };
+    dir.join(format!(
+        "syntaxes-{}-v{}-{:08x}.bin",
+        env!("CARGO_PKG_VERSION"),
+        SYNTAX_CACHE_VERSION,
+        checksum,
+    ))
+}
+
+// Decompressing the embedded syntax set takes about one third of the loading time. The decompressed bytes are cached
+// in the directory and deserialized directly on the next run
+fn load_syntax_set_with_cache(dir: Option<&Path>) -> Result<SyntaxSet> {
+    let path = dir.map(syntax_set_cache_path);
+    if let Some(path) = &path {
+        if let Ok(bytes) = fs::read(path) {
+            if let Ok(syntaxes) = bincode::deserialize(&bytes) {
+                return Ok(syntaxes);
+            }
+        }
+    }
+
+    // Deserializing from the slice is faster than deserializing from the decoder directly
+    let mut bytes = vec![];
+    ZlibDecoder::new(SYNTAX_SET_BIN).read_to_end(&mut bytes)?;
+    let syntaxes = bincode::deserialize(&bytes)?;
+    if let Some(path) = &path {
+        // Failing to write the cache is not an error
+        if write_cache(path, &bytes).is_ok() {
+            remove_stale_caches(path);
+        }
+    }
+    Ok(syntaxes)
+}
+
+// Remove caches written by other versions of hgrep so that they don't remain in the cache directory forever
+fn remove_stale_caches(path: &Path) {
+    let (dir, current) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return,
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if name == current {
+            continue;
+        }
+        if let Some(name) = name.to_str() {
+            if name.starts_with("syntaxes-") && name.ends_with(".bin") {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+// Write the file to the temporary path and rename it so that other processes never read the partially written cache
+fn write_cache(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension(format!("tmp{}", process::id()));
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
 }
 
 pub trait LockableWrite<'a> {
@@ -44,6 +192,39 @@ impl<'a> LockableWrite<'a> for Stdout {
     }
 }
 
+// Files which print more lines than this are streamed to the writer instead of being rendered in memory
+const STREAM_MIN_LINES: u64 = 1000;
+
+// Writer to stream the output of a large file. The lock is taken on the first write so that finding syntax or blames
+// before drawing does not block other threads
+struct StreamWriter<'a, W: LockableWrite<'a>> {
+    writer: &'a W,
+    locked: Option<W::Locked>,
+}
+
+impl<'a, W: LockableWrite<'a>> StreamWriter<'a, W> {
+    fn new(writer: &'a W) -> Self {
+        Self {
+            writer,
+            locked: None,
+        }
+    }
+}
+
+impl<'a, W: LockableWrite<'a>> Write for StreamWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let writer = self.writer;
+        self.locked.get_or_insert_with(|| writer.lock()).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.locked {
+            Some(locked) => locked.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
 pub fn list_themes<W: Write>(out: W, opts: &PrinterOptions<'_>) -> Result<()> {
     let syntaxes = load_syntax_set()?;
     list_themes_with_syntaxes(out, opts, &syntaxes)
@@ -58,7 +239,7 @@ fn list_themes_with_syntaxes<W: Write>(
 
     let themes = {
         let mut m = load_bat_themes()?.themes;
-        m.extend(ThemeSet::load_defaults().themes.into_iter());
+        m.extend(ThemeSet::load_defaults().themes);
         let mut v: Vec<_> = m.into_iter().collect();
         v.sort_by(|l, r| l.0.cmp(&r.0));
         v
@@ -70,15 +251,15 @@ fn list_themes_with_syntaxes<W: Write>(
     themes
         .iter()
         .try_for_each(|(name, theme)| {
-            let mut drawer = Drawer::new(&mut out, opts, theme, &sample_file.chunks);
+            let mut drawer = Drawer::new(&mut out, opts, theme, &sample_file.chunks, None, None);
             drawer.canvas.set_bold()?;
             write!(drawer.canvas, "{:?}", name)?;
             drawer.canvas.draw_newline()?;
             drawer.canvas.draw_sample()?;
             writeln!(drawer.canvas)?;
 
-            let hl = LineHighlighter::new(syntax, theme, syntaxes);
-            drawer.draw_file(&sample_file, hl)?;
+            let mut hl = LineHighlighter::new(syntax, theme, syntaxes);
+            drawer.draw_file(&sample_file, &mut hl)?;
             writeln!(drawer.canvas)
         })
         .ignore_broken_pipe()?;
@@ -124,10 +305,10 @@ struct Token<'line> {
 }
 
 impl<'line> Token<'line> {
-    fn chomp(&mut self) {
+    fn chomp(&mut self, keep_cr: bool) {
         if self.text.ends_with('\n') {
             self.text = &self.text[..self.text.len() - 1];
-            if self.text.ends_with('\r') {
+            if !keep_cr && self.text.ends_with('\r') {
                 self.text = &self.text[..self.text.len() - 1];
             }
         }
@@ -152,14 +333,15 @@ enum DrawEvent {
 struct DrawEvents<'a, 'line: 'a> {
     tokens: &'a [Token<'line>],
     chars_in_token: Chars<'line>,
-    regions: &'a [(usize, usize)],
+    regions: &'a [(usize, usize, usize)],
     current_style: Style,
     in_region: bool,
+    region_pattern: usize, // Index of the pattern matched to the current region
     byte_offset: usize,
 }
 
 impl<'a, 'line: 'a> DrawEvents<'a, 'line> {
-    fn new(tokens: &'a [Token<'line>], regions: &'a [(usize, usize)]) -> Self {
+    fn new(tokens: &'a [Token<'line>], regions: &'a [(usize, usize, usize)]) -> Self {
         let (chars_in_token, current_style, tokens) =
             if let Some((head, tail)) = tokens.split_first() {
                 (head.text.chars(), head.style, tail)
@@ -173,25 +355,40 @@ impl<'a, 'line: 'a> DrawEvents<'a, 'line> {
             regions,
             current_style,
             in_region: false,
+            region_pattern: 0,
             byte_offset: 0,
         }
     }
 
+    // Index of the pattern of the region where the current character is. None means it is outside regions
+    fn region(&self) -> Option<usize> {
+        if self.in_region {
+            Some(self.region_pattern)
+        } else {
+            None
+        }
+    }
+
     fn region_boundary(&mut self) -> RegionBoundary {
         let o = self.byte_offset;
 
-        // Eat done regions
-        let num_done_regions = self.regions.iter().take_while(|(_, e)| *e < o).count();
+        // Eat done regions. The region ending at the offset is also done after its end was notified
+        let in_region = self.in_region;
+        let num_done_regions = self
+            .regions
+            .iter()
+            .take_while(|(_, e, _)| *e < o || *e == o && !in_region)
+            .count();
         if num_done_regions > 0 {
             self.regions = &self.regions[num_done_regions..];
         }
 
         match self.regions.first().copied() {
-            Some((s, e)) if o == s && o < e => RegionBoundary::Start,
-            Some((_, e)) if o == e => {
-                // When the next region is adjcent, skip changing highlight
+            Some((s, e, _)) if o == s && o < e => RegionBoundary::Start,
+            Some((_, e, p)) if o == e => {
+                // When the next region of the same pattern is adjcent, skip changing highlight
                 match self.regions.get(1) {
-                    Some((s, _)) if o == *s => RegionBoundary::NotFound,
+                    Some((s, _, q)) if o == *s && p == *q => RegionBoundary::NotFound,
                     _ => RegionBoundary::End,
                 }
             }
@@ -203,6 +400,7 @@ impl<'a, 'line: 'a> DrawEvents<'a, 'line> {
         match self.region_boundary() {
             RegionBoundary::Start if !self.in_region => {
                 self.in_region = true;
+                self.region_pattern = self.regions[0].2;
                 return DrawEvent::RegionStart;
             }
             RegionBoundary::End if self.in_region => {
@@ -270,12 +468,55 @@ fn weak_blend_fg_color(mut fg: Color, bg: Color) -> Color {
     blend_fg_color(fg, bg)
 }
 
-#[inline]
-fn diff_u8(x: u8, y: u8) -> u8 {
-    if x > y {
-        x - y
-    } else {
-        y - x
+// RGB values of 8 basic colors and 8 bright colors in xterm
+const ANSI16_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+fn nearest_ansi16((r, g, b): (u8, u8, u8)) -> u8 {
+    let dist = |(x, y, z): (u8, u8, u8)| {
+        let (dr, dg, db) = (
+            r.abs_diff(x) as u32,
+            g.abs_diff(y) as u32,
+            b.abs_diff(z) as u32,
+        );
+        dr * dr + dg * dg + db * db
+    };
+    (0..ANSI16_RGB.len())
+        .min_by_key(|i| dist(ANSI16_RGB[*i]))
+        .unwrap() as u8
+}
+
+// Convert the color given by user into the representation in `Palette`. 16 colors and 256 colors
+// are encoded in alpha channel as bat does. See `Canvas::set_color()`
+fn user_color(c: TermColor, support: TermColorSupport) -> Color {
+    let ansi = |r| Color {
+        r,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+    match (c, support) {
+        (TermColor::Ansi(n), TermColorSupport::Ansi16) if n < 16 => ansi(n),
+        (TermColor::Ansi(n), TermColorSupport::Ansi16) => ansi(nearest_ansi16(rgb_from_ansi256(n))),
+        (TermColor::Rgb(r, g, b), TermColorSupport::Ansi16) => ansi(nearest_ansi16((r, g, b))),
+        (TermColor::Ansi(n), _) => ansi(n),
+        (TermColor::Rgb(r, g, b), _) => Color { r, g, b, a: 255 },
     }
 }
 
@@ -309,6 +550,83 @@ impl Palette {
         b: 0,
         a: 0,
     };
+    const RED_COLOR_16: Color = Color {
+        r: 1, // Red
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+    const GREEN_COLOR_16: Color = Color {
+        r: 2, // Green
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+    // Background colors of regions matched to the second and later patterns
+    const PATTERN_REGION_BG_16: [Color; 5] = [
+        Color {
+            r: 6,
+            g: 0,
+            b: 0,
+            a: 0,
+        }, // Cyan
+        Color {
+            r: 5,
+            g: 0,
+            b: 0,
+            a: 0,
+        }, // Magenta
+        Color {
+            r: 2,
+            g: 0,
+            b: 0,
+            a: 0,
+        }, // Green
+        Color {
+            r: 4,
+            g: 0,
+            b: 0,
+            a: 0,
+        }, // Blue
+        Color {
+            r: 1,
+            g: 0,
+            b: 0,
+            a: 0,
+        }, // Red
+    ];
+    const PATTERN_REGION_BG_RGB: [Color; 5] = [
+        Color {
+            r: 0x5f,
+            g: 0xd7,
+            b: 0xff,
+            a: 255,
+        },
+        Color {
+            r: 0xff,
+            g: 0x87,
+            b: 0xd7,
+            a: 255,
+        },
+        Color {
+            r: 0x87,
+            g: 0xd7,
+            b: 0x5f,
+            a: 255,
+        },
+        Color {
+            r: 0x87,
+            g: 0xaf,
+            b: 0xff,
+            a: 255,
+        },
+        Color {
+            r: 0xff,
+            g: 0x87,
+            b: 0x87,
+            a: 255,
+        },
+    ];
     const ANSI16: Palette = Palette {
         foreground: Self::NO_COLOR,
         background: Self::NO_COLOR,
@@ -343,7 +661,7 @@ impl Palette {
                 let avg_fg = color_average(foreground);
                 let avg_bg = color_average(background);
                 // Choose foreground or background looking at distance
-                if diff_u8(avg_fg, avg) > diff_u8(avg_bg, avg) {
+                if avg_fg.abs_diff(avg) > avg_bg.abs_diff(avg) {
                     foreground
                 } else {
                     background
@@ -366,9 +684,45 @@ impl Palette {
         }
     }
 
+    // Foreground and background colors of regions matched to the pattern. The first pattern uses the region colors
+    // of the theme and the others use distinct colors in turn
+    fn region_colors(&self, pattern: usize) -> (Color, Color) {
+        if pattern == 0 {
+            return (self.region_fg, self.region_bg);
+        }
+        let idx = (pattern - 1) % Self::PATTERN_REGION_BG_16.len();
+        if self.foreground.a <= 1 {
+            // Themes for 16 colors or 256 colors
+            (Self::BLACK_COLOR_16, Self::PATTERN_REGION_BG_16[idx])
+        } else {
+            let black = Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            };
+            (black, Self::PATTERN_REGION_BG_RGB[idx])
+        }
+    }
+
     fn is_ansi16(&self) -> bool {
         self.foreground.a == 1 && self.foreground.r <= 7
     }
+
+    fn customize(&mut self, colors: &CustomColors, support: TermColorSupport) {
+        let slots = [
+            (&mut self.match_bg, colors.match_bg),
+            (&mut self.match_lnum_fg, colors.match_lnum_fg),
+            (&mut self.region_fg, colors.region_fg),
+            (&mut self.region_bg, colors.region_bg),
+            (&mut self.gutter_fg, colors.gutter_fg),
+        ];
+        for (slot, color) in slots {
+            if let Some(c) = color {
+                *slot = user_color(c, support);
+            }
+        }
+    }
 }
 
 struct Canvas<W: Write> {
@@ -376,6 +730,7 @@ struct Canvas<W: Write> {
     true_color: bool,
     has_background: bool,
     palette: Palette,
+    region_style: RegionStyle,
     current_fg: Option<Color>,
     current_bg: Option<Color>,
 }
@@ -394,17 +749,19 @@ impl<W: Write> DerefMut for Canvas<W> {
 
 impl<W: Write> Canvas<W> {
     fn new(out: W, opts: &PrinterOptions<'_>, theme: &Theme) -> Self {
-        let palette = if opts.color_support == TermColorSupport::Ansi16 {
+        let mut palette = if opts.color_support == TermColorSupport::Ansi16 {
             Palette::ANSI16
         } else {
             Palette::new(theme)
         };
+        palette.customize(&opts.custom_colors, opts.color_support);
 
         Self {
             out,
             true_color: opts.color_support == TermColorSupport::True,
             has_background: !palette.is_ansi16() && opts.background_color,
             palette,
+            region_style: opts.region_style,
             current_fg: None,
             current_bg: None,
         }
@@ -417,6 +774,14 @@ impl<W: Write> Canvas<W> {
         Ok(())
     }
 
+    // Reset all colors and font styles. Caller must set them again after this call
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.out.write_all(b"\x1b[0m")?;
+        self.current_fg = None;
+        self.current_bg = None;
+        Ok(())
+    }
+
     fn draw_newline(&mut self) -> io::Result<()> {
         writeln!(self.out, "\x1b[0m")?; // Reset on newline to ensure to reset color
         self.current_fg = None;
@@ -429,6 +794,7 @@ impl<W: Write> Canvas<W> {
         // color terminals. Color value is encoded in R. See `to_ansi_color()` in bat/src/terminal.rs
         match c.a {
             0 if c.r <= 7 => write!(self.out, "\x1b[{}m", c.r + code)?, // 16 colors; e.g. 3 => 33 (Yellow), 6 => 36 (Cyan) (code=30)
+            0 if c.r <= 15 => write!(self.out, "\x1b[{}m", c.r - 8 + code + 60)?, // Bright colors; e.g. 12 => 94 (Bright blue) (code=30)
             0 => write!(self.out, "\x1b[{};5;{}m", code + 8, c.r)?, // 256 colors; code=38 for fg, code=48 for bg
             1 => write!(self.out, "\x1b[0m")?, // Pass though. Reset color to set default terminal font color
             _ if self.true_color => {
@@ -496,6 +862,16 @@ impl<W: Write> Canvas<W> {
         Ok(())
     }
 
+    fn set_inverse(&mut self) -> io::Result<()> {
+        self.out.write_all(b"\x1b[7m")?;
+        Ok(())
+    }
+
+    fn unset_inverse(&mut self) -> io::Result<()> {
+        self.out.write_all(b"\x1b[27m")?;
+        Ok(())
+    }
+
     fn set_font_style(&mut self, style: FontStyle) -> io::Result<()> {
         if style.contains(FontStyle::BOLD) {
             self.set_bold()?;
@@ -533,9 +909,34 @@ impl<W: Write> Canvas<W> {
         self.set_font_style(style.font_style)
     }
 
-    fn set_region_color(&mut self) -> io::Result<()> {
-        self.set_fg(self.palette.region_fg)?;
-        self.set_bg(self.palette.region_bg)
+    fn set_region_color(&mut self, pattern: usize) -> io::Result<()> {
+        let (fg, bg) = self.palette.region_colors(pattern);
+        self.set_fg(fg)?;
+        self.set_bg(bg)?;
+        if self.region_style.bold {
+            self.set_bold()?;
+        }
+        if self.region_style.underline {
+            self.set_underline()?;
+        }
+        if self.region_style.inverse {
+            self.set_inverse()?;
+        }
+        Ok(())
+    }
+
+    // Font style of the token is reset here. Caller must set the token's style again after this call
+    fn unset_region_style(&mut self) -> io::Result<()> {
+        if self.region_style.bold {
+            self.unset_bold()?;
+        }
+        if self.region_style.underline {
+            self.unset_underline()?;
+        }
+        if self.region_style.inverse {
+            self.unset_inverse()?;
+        }
+        Ok(())
     }
 
     fn set_gutter_color(&mut self) -> io::Result<()> {
@@ -583,6 +984,8 @@ struct LineChars<'a> {
     down_and_horizontal: &'a str,
     up_and_horizontal: &'a str,
     dashed_horizontal: &'a str,
+    ellipsis: &'a str,
+    overline: &'a str,
 }
 
 const UNICODE_LINE_CHARS: LineChars<'static> = LineChars {
@@ -592,6 +995,8 @@ const UNICODE_LINE_CHARS: LineChars<'static> = LineChars {
     down_and_horizontal: "┬",
     up_and_horizontal: "┴",
     dashed_horizontal: "╶",
+    ellipsis: "…",
+    overline: "‾",
 };
 
 const ASCII_LINE_CHARS: LineChars<'static> = LineChars {
@@ -601,35 +1006,140 @@ const ASCII_LINE_CHARS: LineChars<'static> = LineChars {
     down_and_horizontal: "-",
     up_and_horizontal: "-",
     dashed_horizontal: "-",
+    ellipsis: "~",
+    overline: "^",
 };
 
+// Parse state before parsing the line. Checkpoints of a file are recorded at every `CHECKPOINT_INTERVAL` lines
+#[derive(Clone)]
+struct Checkpoint {
+    lnum: u64,
+    parse_state: ParseState,
+    stack: ScopeStack,
+}
+
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
 // Note: More flexible version of syntect::easy::HighlightLines for our use case
 struct LineHighlighter<'a> {
     hl: Highlighter<'a>,
+    syntax: &'a SyntaxReference,
     parse_state: ParseState,
     hl_state: HighlightState,
     syntaxes: &'a SyntaxSet,
+    // Scope stack updated by skipped lines. Styles are calculated from it when highlighting the next line
+    skipped: Option<ScopeStack>,
+    // Parsing starts at most this number of lines before each chunk with `--approx-highlight`
+    approx: Option<u64>,
+    // Checkpoints are recorded while skipping lines when this is `Some`
+    checkpoints: Option<Vec<Checkpoint>>,
+    // Lines before this line were already parsed when resuming from a checkpoint
+    resumed: u64,
 }
 
 impl<'a> LineHighlighter<'a> {
-    fn new(syntax: &SyntaxReference, theme: &'a Theme, syntaxes: &'a SyntaxSet) -> Self {
+    fn new(syntax: &'a SyntaxReference, theme: &'a Theme, syntaxes: &'a SyntaxSet) -> Self {
         let hl = Highlighter::new(theme);
         let parse_state = ParseState::new(syntax);
         let hl_state = HighlightState::new(&hl, ScopeStack::new());
         Self {
             hl,
+            syntax,
             parse_state,
             hl_state,
             syntaxes,
+            skipped: None,
+            approx: None,
+            checkpoints: None,
+            resumed: 0,
         }
     }
 
-    fn skip_line(&mut self, line: &str) {
-        let ops = self.parse_state.parse_line(line, self.syntaxes);
-        for _ in HighlightIterator::new(&mut self.hl_state, &ops, line, &self.hl) {}
+    // Calculate styles from the scope stack updated by the skipped lines
+    fn sync(&mut self) {
+        if let Some(stack) = self.skipped.take() {
+            self.hl_state = HighlightState::new(&self.hl, stack);
+        }
+    }
+
+    // Swap the parse state and the highlight state. This is used for highlighting replaced lines from the state
+    // before the original line without affecting highlights of the following lines
+    fn swap_state(&mut self, state: (ParseState, HighlightState)) -> (ParseState, HighlightState) {
+        self.sync();
+        let parse_state = mem::replace(&mut self.parse_state, state.0);
+        let hl_state = mem::replace(&mut self.hl_state, state.1);
+        (parse_state, hl_state)
+    }
+
+    fn state(&mut self) -> (ParseState, HighlightState) {
+        self.sync();
+        (self.parse_state.clone(), self.hl_state.clone())
+    }
+
+    fn stack(&mut self) -> &mut ScopeStack {
+        let path = &self.hl_state.path;
+        self.skipped.get_or_insert_with(|| path.clone())
+    }
+
+    // Restore the state from the last checkpoint before the line. Lines before the checkpoint don't need to be parsed
+    fn resume(&mut self, lnum: u64) {
+        let checkpoint = self
+            .checkpoints
+            .as_ref()
+            .and_then(|cs| cs.iter().rev().find(|c| c.lnum <= lnum));
+        if let Some(c) = checkpoint {
+            self.parse_state = c.parse_state.clone();
+            self.skipped = Some(c.stack.clone());
+            self.resumed = c.lnum;
+        }
+    }
+
+    fn record_checkpoint(&mut self, lnum: u64) {
+        if !lnum.is_multiple_of(CHECKPOINT_INTERVAL) {
+            return;
+        }
+        let stack = self.stack().clone();
+        if let Some(checkpoints) = &mut self.checkpoints {
+            if checkpoints.last().map(|c| c.lnum < lnum).unwrap_or(true) {
+                checkpoints.push(Checkpoint {
+                    lnum,
+                    parse_state: self.parse_state.clone(),
+                    stack,
+                });
+            }
+        }
+    }
+
+    // Parse the line before the chunk starting at `start` only for updating the state. Styles of the line are not
+    // calculated since it is not printed
+    fn skip_line(&mut self, line: &[u8], lnum: u64, start: u64) {
+        if lnum < self.resumed {
+            return;
+        }
+        if let Some(max) = self.approx {
+            let distance = start - lnum;
+            if distance > max {
+                return;
+            }
+            if distance == max {
+                // Start parsing from the initial state ignoring all the preceding lines
+                self.parse_state = ParseState::new(self.syntax);
+                self.skipped = Some(ScopeStack::new());
+            }
+        }
+        if self.checkpoints.is_some() {
+            self.record_checkpoint(lnum);
+        }
+        let line = String::from_utf8_lossy(line);
+        let ops = self.parse_state.parse_line(&line, self.syntaxes);
+        let stack = self.stack();
+        for (_, op) in ops.iter() {
+            stack.apply(op);
+        }
     }
 
     fn highlight<'line>(&mut self, line: &'line str) -> Vec<Token<'line>> {
+        self.sync();
         let ops = self.parse_state.parse_line(line, self.syntaxes);
         HighlightIterator::new(&mut self.hl_state, &ops, line, &self.hl)
             .map(|(mut style, text)| {
@@ -677,6 +1187,150 @@ impl<'a> Iterator for LinesInclusive<'a> {
     }
 }
 
+// Break opportunities for word-wise text-wrapping. Lines can be broken before and after a wide character
+// since languages using wide characters (e.g. Japanese) don't put spaces between words.
+#[inline]
+fn breaks_after(c: char, width: usize) -> bool {
+    c.is_whitespace() || c.is_ascii_punctuation() || width > 1
+}
+
+// Width of the extended grapheme cluster on terminal. Characters following the first one (combining marks,
+// ZWJ sequences, skin tone modifiers, ...) are rendered within the width of the first character, except for
+// emoji presentation selector (VS16) and a pair of regional indicators (flag) which occupy 2 columns.
+fn grapheme_width(g: &str) -> usize {
+    let mut chars = g.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return 0,
+    };
+    let width = first.width_cjk().unwrap_or(0);
+    if chars.next().is_none() {
+        return width;
+    }
+    if g.contains('\u{fe0f}') || ('\u{1f1e6}'..='\u{1f1ff}').contains(&first) {
+        return 2;
+    }
+    width
+}
+
+// Extended grapheme clusters in a line. Since characters in a line are drawn in order, each cluster is looked
+// up at its first character.
+struct Clusters<'a> {
+    iter: Peekable<GraphemeIndices<'a>>,
+}
+
+impl<'a> Clusters<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            iter: text.grapheme_indices(true).peekable(),
+        }
+    }
+
+    // Returns the cluster starting at the byte offset. None means the offset is in the middle of a cluster
+    fn starting_at(&mut self, offset: usize) -> Option<&'a str> {
+        while let Some(&(idx, cluster)) = self.iter.peek() {
+            if idx > offset {
+                return None;
+            }
+            self.iter.next();
+            if idx == offset {
+                return Some(cluster);
+            }
+        }
+        None
+    }
+}
+
+// Visible symbol for the invisible character on showing all characters. Tab and space are handled by caller
+// since their symbols depend on the position in the line.
+fn invisible_symbol(c: char) -> Option<Cow<'static, str>> {
+    let s = match c {
+        '\x00'..='\x1f' => {
+            // Control Pictures block (U+2400..U+241F)
+            let c = char::from_u32(0x2400 + c as u32).unwrap();
+            return Some(c.to_string().into());
+        }
+        '\x7f' => "␡",
+        '\u{a0}' => "⍽",
+        '\u{80}'..='\u{9f}'
+        | '\u{ad}'
+        | '\u{200b}'..='\u{200f}'
+        | '\u{202a}'..='\u{202e}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}'
+        | '\u{feff}' => return Some(format!("\\u{{{:x}}}", c as u32).into()),
+        _ => return None,
+    };
+    Some(s.into())
+}
+
+// Visible symbol for the invisible character in a grapheme cluster of multiple characters (e.g. NBSP followed by
+// VS16). ZWJ and ZWNJ are not shown since they join characters in the cluster such as emoji ZWJ sequences.
+fn invisible_symbol_in_cluster(c: char) -> Option<Cow<'static, str>> {
+    match c {
+        '\u{200c}' | '\u{200d}' => None,
+        _ => invisible_symbol(c),
+    }
+}
+
+// Width of the word at the head of the text. The word ends before whitespace or a wide character, or
+// after punctuation.
+fn word_width(text: &str) -> usize {
+    let mut width = 0;
+    for g in text.graphemes(true) {
+        let c = g.chars().next().unwrap(); // OK since grapheme cluster is not empty
+        if c.is_whitespace() {
+            break;
+        }
+        let w = grapheme_width(g);
+        if w > 1 && width > 0 {
+            break;
+        }
+        width += w;
+        if breaks_after(c, w) {
+            break;
+        }
+    }
+    width
+}
+
+// Kind of the line in the diff of replacements on previewing `--replace`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DiffLine {
+    Unchanged,
+    Removed,
+    Added,
+}
+
+const BLAME_AUTHOR_WIDTH: usize = 12;
+const BLAME_DATE_WIDTH: usize = 14; // Enough for "59 minutes ago"
+const BLAME_WIDTH: u16 = 1 + 7 + 1 + BLAME_AUTHOR_WIDTH as u16 + 1 + BLAME_DATE_WIDTH as u16;
+// Blame columns are not drawn when the text body would be narrower than this
+const BLAME_MIN_BODY_WIDTH: u16 = 10;
+
+// Cut the text to fit the width with the ellipsis. Returns the text and its width
+fn truncate_text<'a>(text: &'a str, max: usize, ellipsis: &str) -> (Cow<'a, str>, usize) {
+    let width = text.width_cjk();
+    if width <= max {
+        return (text.into(), width);
+    }
+    let limit = max - ellipsis.width();
+    let mut width = 0;
+    let mut end = 0;
+    for (i, c) in text.char_indices() {
+        let w = c.width_cjk().unwrap_or(0);
+        if width + w > limit {
+            break;
+        }
+        width += w;
+        end = i + c.len_utf8();
+    }
+    (
+        format!("{}{}", &text[..end], ellipsis).into(),
+        width + ellipsis.width(),
+    )
+}
+
 // Drawer is responsible for one-time screen drawing
 struct Drawer<'file, W: Write> {
     grid: bool,
@@ -684,13 +1338,28 @@ struct Drawer<'file, W: Write> {
     lnum_width: u16,
     first_only: bool,
     wrap: bool,
+    word_wrap: bool,
+    wrap_indent: bool,
+    truncate: bool,
+    max_columns: Option<u16>,
+    show_all: bool,
     tab_width: u16,
+    changes: Option<LineChanges>,
+    blames: Option<LineBlames>,
+    replace: bool,
     chars: LineChars<'file>,
     canvas: Canvas<W>,
 }
 
 impl<'file, W: Write> Drawer<'file, W> {
-    fn new(out: W, opts: &PrinterOptions<'_>, theme: &'file Theme, chunks: &[(u64, u64)]) -> Self {
+    fn new(
+        out: W,
+        opts: &PrinterOptions<'_>,
+        theme: &'file Theme,
+        chunks: &[(u64, u64)],
+        changes: Option<LineChanges>,
+        blames: Option<LineBlames>,
+    ) -> Self {
         let last_lnum = chunks.last().map(|(_, e)| *e).unwrap_or(0);
         let mut lnum_width = num_digits(last_lnum);
         if chunks.len() > 1 {
@@ -707,9 +1376,17 @@ impl<'file, W: Write> Drawer<'file, W> {
             grid: opts.grid,
             term_width: opts.term_width,
             lnum_width,
-            wrap: opts.text_wrap == TextWrapMode::Char,
+            wrap: opts.text_wrap == TextWrapMode::Char || opts.text_wrap == TextWrapMode::Word,
+            word_wrap: opts.text_wrap == TextWrapMode::Word,
+            wrap_indent: opts.wrap_indent,
+            truncate: opts.text_wrap == TextWrapMode::Truncate,
+            max_columns: opts.max_columns,
+            show_all: opts.show_all,
             tab_width: opts.tab_width as u16,
             first_only: opts.first_only,
+            changes,
+            blames,
+            replace: false,
             chars,
             canvas: Canvas::new(out, opts, theme),
         }
@@ -717,11 +1394,28 @@ impl<'file, W: Write> Drawer<'file, W> {
 
     #[inline]
     fn gutter_width(&self) -> u16 {
-        if self.grid {
+        let width = if self.grid {
             self.lnum_width + 4
         } else {
             self.lnum_width + 2
+        };
+        width + self.annotations_width()
+    }
+
+    // Width of the columns for diff markers of replacements, Git change markers and blame information in gutter
+    #[inline]
+    fn annotations_width(&self) -> u16 {
+        let mut width = 0;
+        if self.replace {
+            width += 2;
+        }
+        if self.changes.is_some() {
+            width += 2;
         }
+        if self.blames.is_some() {
+            width += BLAME_WIDTH;
+        }
+        width
     }
 
     fn draw_horizontal_line(&mut self, sep: &str) -> io::Result<()> {
@@ -731,26 +1425,42 @@ impl<'file, W: Write> Drawer<'file, W> {
             self.canvas.write_all(self.chars.horizontal.as_bytes())?;
         }
         self.canvas.write_all(sep.as_bytes())?;
-        for _ in 0..self.term_width - gutter_width + 1 {
+        for _ in 0..self.term_width.saturating_sub(gutter_width) + 1 {
             self.canvas.write_all(self.chars.horizontal.as_bytes())?;
         }
         self.canvas.draw_newline()
     }
 
-    fn draw_line_number(&mut self, lnum: u64, matched: bool) -> io::Result<()> {
+    fn draw_line_number(&mut self, lnum: u64, matched: bool, diff: DiffLine) -> io::Result<()> {
         if matched {
             self.canvas.set_match_lnum_color()?;
         } else {
             self.canvas.set_gutter_color()?;
         }
-        let width = num_digits(lnum);
-        self.canvas
-            .draw_spaces((self.lnum_width - width) as usize)?;
-        write!(self.canvas, " {}", lnum)?;
-        if self.grid {
-            if matched {
-                self.canvas.set_gutter_color()?;
+        if diff == DiffLine::Added {
+            // Replaced line does not exist in the file so it has no line number and annotations
+            self.canvas.draw_spaces(self.lnum_width as usize + 1)?;
+            self.draw_diff_marker(diff)?;
+            self.canvas.set_gutter_color()?;
+            self.canvas
+                .draw_spaces((self.annotations_width() - 2) as usize)?;
+        } else {
+            let width = num_digits(lnum);
+            self.canvas
+                .draw_spaces((self.lnum_width - width) as usize)?;
+            write!(self.canvas, " {}", lnum)?;
+            if self.replace {
+                self.draw_diff_marker(diff)?;
             }
+            if self.changes.is_some() {
+                self.draw_change_marker(lnum)?;
+            }
+            if self.blames.is_some() {
+                self.draw_blame(lnum)?;
+            }
+        }
+        if self.grid {
+            self.canvas.set_gutter_color()?;
             write!(self.canvas, " {}", self.chars.vertical)?;
         }
         self.canvas.set_default_bg()?;
@@ -758,9 +1468,51 @@ impl<'file, W: Write> Drawer<'file, W> {
         Ok(()) // Do not reset color because another color text will follow
     }
 
+    fn draw_diff_marker(&mut self, diff: DiffLine) -> io::Result<()> {
+        let (marker, color) = match diff {
+            DiffLine::Removed => ("-", Palette::RED_COLOR_16),
+            DiffLine::Added => ("+", Palette::GREEN_COLOR_16),
+            DiffLine::Unchanged => return self.canvas.draw_spaces(2),
+        };
+        self.canvas.write_all(b" ")?;
+        self.canvas.set_fg(color)?;
+        self.canvas.write_all(marker.as_bytes())
+    }
+
+    fn draw_change_marker(&mut self, lnum: u64) -> io::Result<()> {
+        let change = self.changes.as_ref().and_then(|c| c.get(&lnum)).copied();
+        let (marker, color) = match change {
+            Some(LineChange::Added) => ("+", Palette::GREEN_COLOR_16),
+            Some(LineChange::Modified) => ("~", Palette::YELLOW_COLOR_16),
+            Some(LineChange::RemovedAbove) => (self.chars.overline, Palette::RED_COLOR_16),
+            Some(LineChange::RemovedBelow) => ("_", Palette::RED_COLOR_16),
+            None => return self.canvas.draw_spaces(2),
+        };
+        self.canvas.write_all(b" ")?;
+        self.canvas.set_fg(color)?;
+        self.canvas.write_all(marker.as_bytes())
+    }
+
+    // Draw short commit hash, author and relative date of the line in the dimmed color
+    fn draw_blame(&mut self, lnum: u64) -> io::Result<()> {
+        self.canvas.set_gutter_color()?;
+        let blame = match self.blames.as_ref().and_then(|b| b.get(&lnum)) {
+            Some(blame) => blame,
+            None => return self.canvas.draw_spaces(BLAME_WIDTH as usize),
+        };
+        write!(self.canvas, " {:7} ", blame.commit)?;
+        let (author, width) = truncate_text(&blame.author, BLAME_AUTHOR_WIDTH, self.chars.ellipsis);
+        self.canvas.write_all(author.as_bytes())?;
+        self.canvas.draw_spaces(BLAME_AUTHOR_WIDTH - width)?;
+        let (date, width) = truncate_text(&blame.date, BLAME_DATE_WIDTH, self.chars.ellipsis);
+        write!(self.canvas, " {}", date)?;
+        self.canvas.draw_spaces(BLAME_DATE_WIDTH - width)
+    }
+
     fn draw_wrapping_gutter(&mut self) -> io::Result<()> {
         self.canvas.set_gutter_color()?;
-        self.canvas.draw_spaces(self.lnum_width as usize + 2)?;
+        self.canvas
+            .draw_spaces((self.lnum_width + 2 + self.annotations_width()) as usize)?;
         if self.grid {
             write!(self.canvas, "{} ", self.chars.vertical)?;
         }
@@ -772,15 +1524,15 @@ impl<'file, W: Write> Drawer<'file, W> {
         // + 1 for left margin and - 3 for length of "..."
         let left_margin = self.lnum_width + 1 - 3;
         self.canvas.draw_spaces(left_margin as usize)?;
-        let w = if self.grid {
-            write!(self.canvas, "... {}", self.chars.vertical_and_right)?;
-            5
-        } else {
-            write!(self.canvas, "...")?;
-            3
-        };
+        write!(self.canvas, "...")?;
+        self.canvas.draw_spaces(self.annotations_width() as usize)?;
+        let mut w = 3 + self.annotations_width();
+        if self.grid {
+            write!(self.canvas, " {}", self.chars.vertical_and_right)?;
+            w += 2;
+        }
         self.canvas.set_default_bg()?;
-        let body_width = self.term_width - left_margin - w; // This crashes when terminal width is smaller than gutter
+        let body_width = self.term_width.saturating_sub(left_margin + w);
         for _ in 0..body_width {
             self.canvas
                 .write_all(self.chars.dashed_horizontal.as_bytes())?;
@@ -792,12 +1544,21 @@ impl<'file, W: Write> Drawer<'file, W> {
         &mut self,
         matched: bool,
         style: Style,
-        in_region: bool,
+        region: Option<usize>,
+        indent: usize,
     ) -> io::Result<()> {
         self.canvas.draw_newline()?;
         self.draw_wrapping_gutter()?;
-        if in_region {
-            self.canvas.set_region_color()
+        if indent > 0 {
+            if matched {
+                self.canvas.set_match_bg_color()?;
+            } else {
+                self.canvas.set_default_bg()?;
+            }
+            self.canvas.draw_spaces(indent)?;
+        }
+        if let Some(pattern) = region {
+            self.canvas.set_region_color(pattern)
         } else if matched {
             self.canvas.set_match_style(style)
         } else {
@@ -805,89 +1566,410 @@ impl<'file, W: Write> Drawer<'file, W> {
         }
     }
 
-    fn draw_line(
-        &mut self,
-        mut tokens: Vec<Token<'_>>,
-        lnum: u64,
-        regions: Option<Vec<(usize, usize)>>,
-    ) -> io::Result<()> {
-        // The highlighter requires newline at the end. But we don't want it since
-        // - we sometimes need to fill the rest of line with spaces
-        // - we clear colors before writing newline
-        if let Some(tok) = tokens.last_mut() {
-            tok.chomp();
-            if tok.text.is_empty() {
-                tokens.pop(); // As the result of `chomp()`, text may be empty. Empty token can be removed
+    // Width of indentation of continuation lines on text-wrapping. The indentation is omitted when it is too
+    // wide since little space is left for the text
+    fn wrap_indent_width(&self, tokens: &[Token<'_>], body_width: usize) -> usize {
+        if !self.wrap_indent || !self.wrap {
+            return 0;
+        }
+        let mut width = 0;
+        for c in tokens.iter().flat_map(|t| t.text.chars()) {
+            match c {
+                ' ' => width += 1,
+                '\t' if self.tab_width > 0 => {
+                    let tab_width = self.tab_width as usize;
+                    width += tab_width - width % tab_width;
+                }
+                _ => break,
             }
         }
+        if width * 2 > body_width {
+            0
+        } else {
+            width
+        }
+    }
 
-        let body_width = (self.term_width - self.gutter_width()) as usize;
-        let matched = regions.is_some();
+    // Visual width of the grapheme cluster at the visual column `col` of the line. Tab character advances to
+    // the next tab stop
+    fn cluster_width(&self, cluster: &str, col: usize) -> usize {
+        if cluster == "\t" && self.tab_width > 0 {
+            let tab_width = self.tab_width as usize;
+            return tab_width - col % tab_width;
+        }
+        if self.show_all {
+            let mut chars = cluster.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                if let Some(symbol) = invisible_symbol(c) {
+                    return symbol.width();
+                }
+            } else if cluster
+                .chars()
+                .any(|c| invisible_symbol_in_cluster(c).is_some())
+            {
+                // Each character in the cluster is drawn separately when some of them are replaced with symbols
+                return cluster
+                    .chars()
+                    .map(|c| match invisible_symbol_in_cluster(c) {
+                        Some(symbol) => symbol.width(),
+                        None => c.width_cjk().unwrap_or(0),
+                    })
+                    .sum();
+            }
+        }
+        grapheme_width(cluster)
+    }
 
-        let tokens = tokens.as_slice();
-        let regions = regions.as_ref().map(AsRef::as_ref).unwrap_or(&[][..]);
-        let mut events = DrawEvents::new(tokens, regions);
+    // Draw the invisible character with a visible symbol in dimmed color on showing all characters. `trailing` is
+    // true when the character is a part of trailing whitespaces. `in_cluster` is true when the character is in a
+    // grapheme cluster of multiple characters. Returns false when the character is visible.
+    fn draw_invisible(
+        &mut self,
+        c: char,
+        width: usize,
+        trailing: bool,
+        in_cluster: bool,
+        events: &DrawEvents<'_, '_>,
+    ) -> io::Result<bool> {
+        if !self.show_all {
+            return Ok(false);
+        }
+        let symbol = match c {
+            '\t' => match width {
+                0 => "".into(),
+                1 => "↹".into(),
+                _ => format!("├{}┤", "─".repeat(width - 2)).into(),
+            },
+            ' ' if trailing => "·".into(),
+            _ if in_cluster => match invisible_symbol_in_cluster(c) {
+                Some(s) => s,
+                None => return Ok(false),
+            },
+            _ => match invisible_symbol(c) {
+                Some(s) => s,
+                None => return Ok(false),
+            },
+        };
+        self.canvas.set_fg(self.canvas.palette.gutter_fg)?;
+        self.canvas.write_all(symbol.as_bytes())?;
+        if let Some(pattern) = events.region() {
+            let (fg, _) = self.canvas.palette.region_colors(pattern);
+            self.canvas.set_fg(fg)?;
+        } else {
+            self.canvas.set_fg(events.current_style.foreground)?;
+        }
+        Ok(true)
+    }
+
+    // Calculate the window of visual columns of the line which is shown on truncating the line. The window
+    // is centered on the first matched region. None means the line does not need to be truncated.
+    fn truncation_window(
+        &self,
+        text: &str,
+        regions: &[(usize, usize, usize)],
+        limit: usize,
+    ) -> Option<(usize, usize)> {
+        let (region_start, region_end, _) = regions.first().copied().unwrap_or((0, 0, 0));
+        let (mut col_start, mut col_end) = (0, 0);
+        let mut total = 0;
+        for (offset, cluster) in text.grapheme_indices(true) {
+            let end = offset + cluster.len();
+            let w = self.cluster_width(cluster, total);
+            // Boundaries of the region may be in the middle of a cluster
+            if offset <= region_start && region_start < end {
+                col_start = total;
+            }
+            if offset < region_end && region_end <= end {
+                col_end = total + w;
+            }
+            total += w;
+        }
+        if total <= limit {
+            return None;
+        }
+
+        let marker = self.chars.ellipsis.width();
+        if col_end + marker <= limit {
+            return Some((0, limit - marker)); // The region is visible without clipping head of the line
+        }
+        let text_width = limit.saturating_sub(marker * 2);
+        let center = (col_start + col_end) / 2;
+        let start = cmp::min(center.saturating_sub(text_width / 2), col_start);
+        if start + text_width + marker >= total {
+            return Some((total - limit.saturating_sub(marker), total)); // Tail of the line is visible
+        }
+        Some((start, start + text_width))
+    }
 
-        self.draw_line_number(lnum, matched)?;
+    fn draw_ellipsis(&mut self, matched: bool) -> io::Result<()> {
+        self.canvas.reset_style()?;
+        self.canvas.set_gutter_color()?;
         if matched {
-            self.canvas.set_match_style(events.current_style)?;
-        } else if !tokens.is_empty() {
-            self.canvas.set_style(events.current_style)?;
+            self.canvas.set_match_bg_color()?;
         }
+        self.canvas.write_all(self.chars.ellipsis.as_bytes())
+    }
 
-        let mut width = 0; // Text width written to terminal
-        let mut saw_zwj = false;
+    fn set_token_style(
+        &mut self,
+        prev_style: Style,
+        style: Style,
+        matched: bool,
+    ) -> io::Result<()> {
+        self.canvas.unset_font_style(prev_style.font_style)?;
+        if !matched {
+            self.canvas.set_background(style.background)?;
+        }
+        self.canvas.set_fg(style.foreground)?;
+        self.canvas.set_font_style(style.font_style)
+    }
+
+    // Draw the part of line within the window of visual columns. Characters and styles outside the window are
+    // skipped. Returns the width written to terminal.
+    fn draw_truncated_text(
+        &mut self,
+        events: &mut DrawEvents<'_, '_>,
+        text: &str,
+        trailing: usize,
+        matched: bool,
+        (start, end): (usize, usize),
+    ) -> io::Result<usize> {
+        let mut clusters = Clusters::new(text);
+        let mut width = 0;
+        let mut col = 0;
+        let mut visible = start == 0;
+        let mut cluster_drawn = false;
         loop {
             match events.next_event() {
-                DrawEvent::Char('\t') if self.tab_width > 0 => {
-                    let w = self.tab_width as usize;
-                    if width + w > body_width && self.wrap {
-                        self.canvas.draw_spaces(body_width - width)?;
-                        self.draw_text_wrappping(matched, events.current_style, events.in_region)?;
-                        width = 0;
-                    } else {
-                        self.canvas.draw_spaces(w)?;
-                        width += w;
-                    }
-                }
                 DrawEvent::Char(c) => {
-                    // Handle zero width joiner
-                    let w = if c == '\u{200d}' {
-                        saw_zwj = true;
-                        0
-                    } else if saw_zwj {
-                        saw_zwj = false;
-                        0 // Do not count width while joining current character into previous one with ZWJ
-                    } else {
-                        c.width_cjk().unwrap_or(0)
+                    let offset = events.byte_offset - c.len_utf8();
+                    let cluster = match clusters.starting_at(offset) {
+                        Some(cluster) => cluster,
+                        None => {
+                            // Rest of characters in the cluster follow the first character
+                            if cluster_drawn && !self.draw_invisible(c, 0, false, true, events)? {
+                                write!(self.canvas, "{}", c)?;
+                            }
+                            continue;
+                        }
                     };
-                    if width + w > body_width && self.wrap {
-                        self.canvas.draw_spaces(body_width - width)?;
-                        self.draw_text_wrappping(matched, events.current_style, events.in_region)?;
-                        width = 0;
+                    cluster_drawn = false;
+                    let w = self.cluster_width(cluster, col);
+                    let (l, r) = (col, col + w);
+                    col = r;
+                    if r <= start && l < start {
+                        continue; // Before the window
                     }
-                    write!(self.canvas, "{}", c)?;
-                    width += w;
-                }
-                DrawEvent::TokenBoundary(prev_style) => {
-                    if !events.in_region {
-                        self.canvas.unset_font_style(prev_style.font_style)?;
-                        if !matched {
-                            self.canvas
-                                .set_background(events.current_style.background)?;
+                    if l > end || l == end && w > 0 {
+                        break;
+                    }
+
+                    if !visible {
+                        visible = true;
+                        self.draw_ellipsis(matched)?;
+                        width += self.chars.ellipsis.width();
+                        if let Some(pattern) = events.region() {
+                            self.canvas.set_region_color(pattern)?;
+                        } else if matched {
+                            self.canvas.set_match_style(events.current_style)?;
+                        } else {
+                            self.canvas.set_style(events.current_style)?;
                         }
-                        self.canvas.set_fg(events.current_style.foreground)?;
-                        self.canvas
-                            .set_font_style(events.current_style.font_style)?;
                     }
+
+                    if l < start {
+                        // Wide character is clipped at start of the window
+                        self.canvas.draw_spaces(r - start)?;
+                        width += r - start;
+                    } else if r > end {
+                        // Wide character is clipped at end of the window
+                        self.canvas.draw_spaces(end - l)?;
+                        width += end - l;
+                        break;
+                    } else if self.draw_invisible(
+                        c,
+                        w,
+                        offset >= trailing,
+                        cluster.len() > c.len_utf8(),
+                        events,
+                    )? {
+                        width += w;
+                        cluster_drawn = true;
+                    } else if c == '\t' && self.tab_width > 0 {
+                        self.canvas.draw_spaces(w)?;
+                        width += w;
+                    } else {
+                        write!(self.canvas, "{}", c)?;
+                        width += w;
+                        cluster_drawn = true;
+                    }
+                }
+                DrawEvent::TokenBoundary(prev_style) if visible && !events.in_region => {
+                    self.set_token_style(prev_style, events.current_style, matched)?;
                 }
-                DrawEvent::RegionStart => {
-                    self.canvas.set_region_color()?;
+                DrawEvent::RegionStart if visible => {
+                    self.canvas.set_region_color(events.region_pattern)?;
                 }
-                DrawEvent::RegionEnd => {
+                DrawEvent::RegionEnd if visible => {
+                    self.canvas.unset_region_style()?;
                     self.canvas.set_match_style(events.current_style)?;
                 }
-                DrawEvent::Done => break,
+                DrawEvent::Done => return Ok(width),
+                _ => { /* Styles before the window are not necessary */ }
+            }
+        }
+
+        self.draw_ellipsis(matched)?;
+        Ok(width + self.chars.ellipsis.width())
+    }
+
+    fn draw_line(
+        &mut self,
+        mut tokens: Vec<Token<'_>>,
+        lnum: u64,
+        regions: Option<Vec<(usize, usize, usize)>>,
+        diff: DiffLine,
+    ) -> io::Result<()> {
+        // The highlighter requires newline at the end. But we don't want it since
+        // - we sometimes need to fill the rest of line with spaces
+        // - we clear colors before writing newline
+        if let Some(tok) = tokens.last_mut() {
+            tok.chomp(self.show_all); // Carriage return is shown on showing all characters
+            if tok.text.is_empty() {
+                tokens.pop(); // As the result of `chomp()`, text may be empty. Empty token can be removed
+            }
+        }
+
+        let body_width = self.term_width.saturating_sub(self.gutter_width()) as usize;
+        let matched = regions.is_some();
+
+        let tokens = tokens.as_slice();
+        let regions = regions.as_ref().map(AsRef::as_ref).unwrap_or(&[][..]);
+        let mut events = DrawEvents::new(tokens, regions);
+
+        self.draw_line_number(lnum, matched, diff)?;
+        if matched {
+            self.canvas.set_match_style(events.current_style)?;
+        } else if !tokens.is_empty() {
+            self.canvas.set_style(events.current_style)?;
+        }
+
+        // Concatenated text of the line to look ahead grapheme clusters and words
+        let text: String = tokens.iter().map(|t| t.text).collect();
+        let trailing = text.trim_end_matches([' ', '\t', '\r']).len(); // Start of trailing whitespaces
+        let window = if self.truncate {
+            let limit = match self.max_columns {
+                Some(max) => cmp::min(max as usize, body_width),
+                None => body_width,
+            };
+            self.truncation_window(&text, regions, limit)
+        } else {
+            None
+        };
+
+        let mut width = 0; // Text width written to terminal
+        if let Some(window) = window {
+            width = self.draw_truncated_text(&mut events, &text, trailing, matched, window)?;
+        } else {
+            let indent = self.wrap_indent_width(tokens, body_width);
+            let mut clusters = Clusters::new(&text);
+
+            let mut col = 0; // Visual column in the line. Unlike `width`, this is not reset on text-wrapping
+            let mut at_word_start = true;
+            loop {
+                match events.next_event() {
+                    DrawEvent::Char('\t') if self.tab_width > 0 => {
+                        let w = self.cluster_width("\t", col);
+                        col += w;
+                        if width + w > body_width && self.wrap {
+                            let rest = body_width.saturating_sub(width);
+                            if !self.draw_invisible('\t', rest, false, false, &events)? {
+                                self.canvas.draw_spaces(rest)?;
+                            }
+                            self.draw_text_wrappping(
+                                matched,
+                                events.current_style,
+                                events.region(),
+                                indent,
+                            )?;
+                            width = indent;
+                        } else {
+                            if !self.draw_invisible('\t', w, false, false, &events)? {
+                                self.canvas.draw_spaces(w)?;
+                            }
+                            width += w;
+                        }
+                        at_word_start = true;
+                    }
+                    DrawEvent::Char(c) => {
+                        let offset = events.byte_offset - c.len_utf8();
+                        let cluster = match clusters.starting_at(offset) {
+                            Some(cluster) => cluster,
+                            None => {
+                                // Rest of characters in the cluster are drawn within the width of the first one
+                                if !self.draw_invisible(c, 0, false, true, &events)? {
+                                    write!(self.canvas, "{}", c)?;
+                                }
+                                continue;
+                            }
+                        };
+                        let w = self.cluster_width(cluster, col);
+                        col += w;
+
+                        if self.word_wrap && at_word_start && !c.is_whitespace() && width > indent {
+                            let word = word_width(&text[offset..]);
+                            // When the word is longer than one line, fall back to character-wise text-wrapping
+                            if width + word > body_width && indent + word <= body_width {
+                                if events.in_region {
+                                    // Padding before the word is not a part of the region
+                                    self.canvas.unset_region_style()?;
+                                    self.canvas.set_match_bg_color()?;
+                                }
+                                self.canvas.draw_spaces(body_width.saturating_sub(width))?;
+                                self.draw_text_wrappping(
+                                    matched,
+                                    events.current_style,
+                                    events.region(),
+                                    indent,
+                                )?;
+                                width = indent;
+                            }
+                        }
+                        at_word_start = breaks_after(c, w);
+
+                        if width + w > body_width && self.wrap {
+                            self.canvas.draw_spaces(body_width.saturating_sub(width))?;
+                            self.draw_text_wrappping(
+                                matched,
+                                events.current_style,
+                                events.region(),
+                                indent,
+                            )?;
+                            width = indent;
+                            if self.word_wrap && c == ' ' {
+                                continue; // Do not start continuation line with a space
+                            }
+                        }
+                        let in_cluster = cluster.len() > c.len_utf8();
+                        if !self.draw_invisible(c, w, offset >= trailing, in_cluster, &events)? {
+                            write!(self.canvas, "{}", c)?;
+                        }
+                        width += w;
+                    }
+                    DrawEvent::TokenBoundary(prev_style) => {
+                        if !events.in_region {
+                            self.set_token_style(prev_style, events.current_style, matched)?;
+                        }
+                    }
+                    DrawEvent::RegionStart => {
+                        self.canvas.set_region_color(events.region_pattern)?;
+                    }
+                    DrawEvent::RegionEnd => {
+                        self.canvas.unset_region_style()?;
+                        self.canvas.set_match_style(events.current_style)?;
+                    }
+                    DrawEvent::Done => break,
+                }
             }
         }
 
@@ -903,31 +1985,52 @@ impl<'file, W: Write> Drawer<'file, W> {
         self.canvas.draw_newline()
     }
 
-    fn draw_body(&mut self, file: &File, mut hl: LineHighlighter<'_>) -> io::Result<()> {
+    fn draw_body(&mut self, file: &File, hl: &mut LineHighlighter<'_>) -> io::Result<()> {
         assert!(!file.chunks.is_empty());
 
         let mut matched = file.line_matches.as_ref();
         let mut chunks = file.chunks.iter();
         let mut chunk = chunks.next().unwrap(); // OK since chunks is not empty
+        hl.resume(chunk.0);
 
         for Line(bytes, lnum) in LinesInclusive::new(&file.contents) {
             let (start, end) = *chunk;
             if lnum < start {
-                hl.skip_line(String::from_utf8_lossy(bytes).as_ref()); // Discard parsed result
+                hl.skip_line(bytes, lnum, start);
                 continue;
             }
             if start <= lnum && lnum <= end {
-                let regions = match matched.split_first() {
+                let (regions, replaced) = match matched.split_first() {
                     Some((m, ms)) if m.line_number == lnum => {
                         matched = ms;
-                        Some(m.ranges.clone()) // XXX: Cannot move out ranges in line match
+                        // XXX: Cannot move out ranges in line match
+                        (Some(m.ranges.clone()), m.replaced.as_deref())
                     }
-                    _ => None,
+                    _ => (None, None),
+                };
+                let diff = if replaced.is_some() {
+                    DiffLine::Removed
+                } else {
+                    DiffLine::Unchanged
                 };
+                let replaced = replaced.filter(|r| !r.is_empty());
+                let state = replaced.map(|_| hl.state());
                 let line = String::from_utf8_lossy(bytes);
                 // Collect to `Vec` rather than handing HighlightIterator as-is. HighlightIterator takes ownership of Highlighter
                 // while the iteration. When the highlighter is stored in `self`, it means the iterator takes ownership of `self`.
-                self.draw_line(hl.highlight(line.as_ref()), lnum, regions)?;
+                self.draw_line(hl.highlight(line.as_ref()), lnum, regions, diff)?;
+
+                if let (Some(replaced), Some(state)) = (replaced, state) {
+                    // Highlight the replaced lines as if they were put instead of the original line
+                    let state = hl.swap_state(state);
+                    for r in replaced {
+                        let mut line = String::from_utf8_lossy(&r.text).into_owned();
+                        line.push('\n'); // The highlighter requires newline at the end
+                        let regions = Some(r.ranges.clone());
+                        self.draw_line(hl.highlight(&line), lnum, regions, DiffLine::Added)?;
+                    }
+                    hl.swap_state(state);
+                }
 
                 if lnum == end {
                     if self.first_only {
@@ -971,13 +2074,29 @@ impl<'file, W: Write> Drawer<'file, W> {
         Ok(())
     }
 
-    fn draw_file(&mut self, file: &File, hl: LineHighlighter) -> io::Result<()> {
+    fn draw_file(&mut self, file: &File, hl: &mut LineHighlighter) -> io::Result<()> {
+        self.replace = file.line_matches.iter().any(|m| m.replaced.is_some());
+        // Blame columns are wide. Drop them instead of breaking the layout in a narrow terminal
+        if self.blames.is_some() && self.gutter_width() + BLAME_MIN_BODY_WIDTH > self.term_width {
+            self.blames = None;
+        }
         self.draw_header(&file.path)?;
         self.draw_body(file, hl)?;
         self.draw_footer()
     }
 }
 
+// Check the theme name without loading theme sets
+fn validate_theme(name: Option<&str>) -> Result<()> {
+    match name {
+        Some(name) if !BAT_THEME_NAMES.contains(&name) && !DEFAULT_THEME_NAMES.contains(&name) => {
+            let msg = format!("Unknown theme '{}'. See --list-themes output", name);
+            Err(PrintError::new(msg).into())
+        }
+        _ => Ok(()),
+    }
+}
+
 fn load_themes(name: Option<&str>) -> Result<ThemeSet> {
     let bat_defaults: ThemeSet = load_bat_themes()?;
     match name {
@@ -1026,9 +2145,26 @@ where
     for<'a> W: LockableWrite<'a>,
 {
     writer: W, // Protected with mutex because it should print file by file
-    syntaxes: SyntaxSet,
-    themes: ThemeSet,
+    // Syntax set is loaded on drawing the first file since loading it takes the most time on startup. Nothing is
+    // loaded when no match is found
+    syntaxes: OnceCell<SyntaxSet>,
+    // Theme set is also loaded on drawing the first file. Only the theme name is validated on creating the printer
+    themes: OnceCell<ThemeSet>,
     opts: PrinterOptions<'main>,
+    // Checkpoints of parse states are cached only when the same file may be drawn again such as in server mode
+    cache_checkpoints: bool,
+}
+
+// Key of cached checkpoints. The address of syntax set, the syntax name, the file path and the hash of the contents
+type CheckpointKey = (usize, String, PathBuf, u64);
+
+// Maximum number of files whose checkpoints are cached
+const MAX_CHECKPOINT_FILES: usize = 64;
+
+thread_local! {
+    // Checkpoints of parse states per file to avoid parsing the same lines when the file is drawn again. They are
+    // cached per thread since `ParseState` cannot be sent to other threads
+    static CHECKPOINTS: RefCell<HashMap<CheckpointKey, Vec<Checkpoint>>> = RefCell::new(HashMap::new());
 }
 
 impl<'main> SyntectPrinter<'main, Stdout> {
@@ -1042,28 +2178,39 @@ where
     for<'a> W: LockableWrite<'a>,
 {
     pub fn new(writer: W, opts: PrinterOptions<'main>) -> Result<Self> {
+        validate_theme(opts.theme)?;
         Ok(Self {
             writer,
-            syntaxes: load_syntax_set()?,
-            themes: load_themes(opts.theme)?,
+            syntaxes: OnceCell::new(),
+            themes: OnceCell::new(),
             opts,
+            cache_checkpoints: false,
         })
     }
 
     pub fn with_assets(assets: SyntectAssets, writer: W, opts: PrinterOptions<'main>) -> Self {
         Self {
             writer,
-            syntaxes: assets.syntax_set,
-            themes: assets.theme_set,
+            syntaxes: OnceCell::from(assets.syntax_set),
+            themes: OnceCell::from(assets.theme_set),
             opts,
+            cache_checkpoints: false,
         }
     }
 
+    // Cache checkpoints of parse states to draw the same file faster next time. This is useful only when files
+    // are drawn repeatedly since hashing the contents of the file is needed to look up the cache
+    #[cfg(feature = "server")]
+    pub(crate) fn cache_checkpoints(&mut self, yes: bool) -> &mut Self {
+        self.cache_checkpoints = yes;
+        self
+    }
+
     pub fn writer_mut(&mut self) -> &mut W {
         &mut self.writer
     }
 
-    fn theme(&self) -> &Theme {
+    fn theme(&self) -> Result<&Theme> {
         let name = self.opts.theme.unwrap_or_else(|| {
             if self.opts.color_support == TermColorSupport::Ansi16 {
                 "ansi"
@@ -1071,10 +2218,158 @@ where
                 "Monokai Extended" // Our 25bit -> 8bit color conversion works really well with this colorscheme
             }
         });
-        &self.themes.themes[name]
+        let themes = self
+            .themes
+            .get_or_try_init(|| load_themes(self.opts.theme))?;
+        Ok(&themes.themes[name])
+    }
+
+    // The column for Git change markers is reserved even if the file is not in a Git repository so that
+    // gutters of all files have the same width
+    fn line_changes(&self, path: &Path) -> Option<LineChanges> {
+        if !self.opts.git_changes {
+            return None;
+        }
+        #[cfg(feature = "git")]
+        {
+            Some(crate::git::line_changes(path).unwrap_or_default())
+        }
+        #[cfg(not(feature = "git"))]
+        {
+            let _ = path;
+            Some(LineChanges::new())
+        }
+    }
+
+    // Like the column for Git change markers, the column for blame is reserved even if no blame is available
+    fn line_blames(&self, file: &File) -> Option<LineBlames> {
+        let lines = self.opts.blame?;
+        #[cfg(feature = "git")]
+        {
+            use crate::printer::BlameLines;
+            let blames = match lines {
+                BlameLines::Matched => crate::git::line_blames(
+                    &file.path,
+                    file.line_matches.iter().map(|m| m.line_number),
+                ),
+                BlameLines::All => crate::git::line_blames(
+                    &file.path,
+                    file.chunks.iter().flat_map(|(s, e)| *s..=*e),
+                ),
+            };
+            Some(blames.unwrap_or_default())
+        }
+        #[cfg(not(feature = "git"))]
+        {
+            let _ = (lines, file);
+            Some(LineBlames::new())
+        }
+    }
+
+    fn draw_to<O: Write>(&self, file: &File, out: O) -> Result<()> {
+        use crate::io::IgnoreBrokenPipe;
+
+        if file.chunks.is_empty() || file.line_matches.is_empty() {
+            return Ok(());
+        }
+
+        let theme = self.theme()?;
+        let syntax = self.find_syntax(file)?;
+
+        let changes = self.line_changes(&file.path);
+        let blames = self.line_blames(file);
+
+        let mut hl = LineHighlighter::new(syntax, theme, self.syntaxes()?);
+        hl.approx = self.opts.approx_highlight;
+        // Checkpoints are not useful when all chunks are near the beginning of the file or when parsing starts
+        // right before each chunk
+        let cached = self.cache_checkpoints && hl.approx.is_none();
+        let key = if cached && file.chunks[0].0 > CHECKPOINT_INTERVAL {
+            let mut hasher = DefaultHasher::new();
+            file.contents.hash(&mut hasher);
+            let syntaxes = self.syntaxes()? as *const SyntaxSet as usize;
+            let key = (
+                syntaxes,
+                syntax.name.clone(),
+                file.path.clone(),
+                hasher.finish(),
+            );
+            let cached = CHECKPOINTS.with(|c| c.borrow_mut().remove(&key));
+            hl.checkpoints = Some(cached.unwrap_or_default());
+            Some(key)
+        } else {
+            None
+        };
+
+        Drawer::new(out, &self.opts, theme, &file.chunks, changes, blames)
+            .draw_file(file, &mut hl)
+            .ignore_broken_pipe()?;
+
+        if let (Some(key), Some(checkpoints)) = (key, hl.checkpoints) {
+            CHECKPOINTS.with(|c| {
+                let mut cache = c.borrow_mut();
+                if cache.len() >= MAX_CHECKPOINT_FILES {
+                    cache.clear();
+                }
+                cache.insert(key, checkpoints);
+            });
+        }
+        Ok(())
     }
 
-    fn find_syntax(&self, path: &Path) -> Result<&SyntaxReference> {
+    pub(crate) fn draw(&self, file: &File) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.draw_to(file, &mut buf)?;
+        Ok(buf)
+    }
+
+    // Draw the file to the writer chunk by chunk while holding the lock. Memory usage is bounded by the buffer size
+    fn stream(&self, file: &File) -> Result<()> {
+        use crate::io::IgnoreBrokenPipe;
+
+        let mut out = io::BufWriter::new(StreamWriter::new(&self.writer));
+        self.draw_to(file, &mut out)?;
+        Ok(out.flush().ignore_broken_pipe()?)
+    }
+
+    fn is_large(file: &File) -> bool {
+        let lines: u64 = file.chunks.iter().map(|(s, e)| e - s + 1).sum();
+        lines >= STREAM_MIN_LINES
+    }
+
+    fn write_output(&self, buf: &[u8]) -> Result<()> {
+        use crate::io::IgnoreBrokenPipe;
+
+        if buf.is_empty() {
+            return Ok(());
+        }
+        // Take lock here to print files in serial from multiple threads
+        let mut output = self.writer.lock();
+        output.write_all(buf).ignore_broken_pipe()?;
+        Ok(output.flush()?)
+    }
+
+    // Other threads wait for the syntax set while one thread is loading it
+    fn syntaxes(&self) -> Result<&SyntaxSet> {
+        self.syntaxes.get_or_try_init(load_syntax_set)
+    }
+
+    // Find syntax in the same way as `SyntaxSet::find_syntax_for_file` but the first line is taken from the contents
+    // instead of reading the file again. Syntax of a compressed file such as `access.log.gz` is the one of the file
+    // inside it
+    fn find_syntax(&self, file: &File) -> Result<&SyntaxReference> {
+        let syntaxes = self.syntaxes()?;
+        if let Some(lang) = self.opts.language {
+            return syntaxes.find_syntax_by_token(lang).ok_or_else(|| {
+                let msg = format!(
+                    "Unknown language '{}'. Syntax name or file extension such as 'Rust' or 'rs' is expected",
+                    lang,
+                );
+                PrintError::new(msg).into()
+            });
+        }
+
+        let path = decompress::inner_path(&file.path);
         let name = match path.extension().and_then(OsStr::to_str) {
             Some("fs") => Some("F#"),
             Some("h") => Some("C++"),
@@ -1085,14 +2380,21 @@ where
             Some(".clang-format") => Some("YAML"),
             _ => None,
         });
-        if let Some(syntax) = name.and_then(|n| self.syntaxes.find_syntax_by_name(n)) {
+        if let Some(syntax) = name.and_then(|n| syntaxes.find_syntax_by_name(n)) {
             return Ok(syntax);
         }
 
-        Ok(self
-            .syntaxes
-            .find_syntax_for_file(path)?
-            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text()))
+        let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+        let extension = path.extension().and_then(OsStr::to_str).unwrap_or("");
+        let first_line = file.contents.split(|b| *b == b'\n').next().unwrap_or(&[]);
+        Ok(syntaxes
+            .find_syntax_by_extension(file_name)
+            .or_else(|| syntaxes.find_syntax_by_extension(extension))
+            .or_else(|| {
+                let line = String::from_utf8_lossy(first_line);
+                syntaxes.find_syntax_by_first_line(&line)
+            })
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text()))
     }
 }
 
@@ -1101,23 +2403,26 @@ where
     for<'a> W: LockableWrite<'a>,
 {
     fn print(&self, file: File) -> Result<()> {
-        use crate::io::IgnoreBrokenPipe;
-
-        if file.chunks.is_empty() || file.line_matches.is_empty() {
-            return Ok(());
+        if Self::is_large(&file) {
+            return self.stream(&file);
         }
+        let buf = self.draw(&file)?;
+        self.write_output(&buf)
+    }
 
-        let mut buf = vec![];
-        let theme = self.theme();
-        let syntax = self.find_syntax(&file.path)?;
-
-        let hl = LineHighlighter::new(syntax, theme, &self.syntaxes);
-        Drawer::new(&mut buf, &self.opts, theme, &file.chunks).draw_file(&file, hl)?;
+    // Large files are not rendered in advance. They are streamed when they are written
+    fn render(&self, file: File) -> Result<Rendered> {
+        if Self::is_large(&file) {
+            return Ok(Rendered::File(file));
+        }
+        Ok(Rendered::Bytes(self.draw(&file)?))
+    }
 
-        // Take lock here to print files in serial from multiple threads
-        let mut output = self.writer.lock();
-        output.write_all(&buf).ignore_broken_pipe()?;
-        Ok(output.flush()?)
+    fn write_rendered(&self, rendered: Rendered) -> Result<()> {
+        match rendered {
+            Rendered::Bytes(buf) => self.write_output(&buf),
+            Rendered::File(file) => self.print(file),
+        }
     }
 }
 
@@ -1125,6 +2430,8 @@ where
 mod tests {
     use super::*;
     use crate::chunk::{File, LineMatch};
+    use crate::git::Blame;
+    use crate::grep::Replacement;
     use lazy_static::lazy_static;
     use std::cell::{RefCell, RefMut};
     use std::fmt;
@@ -1178,7 +2485,7 @@ mod tests {
                     let mut base = 0;
                     while let (Some(start), Some(i)) = (l.find("*match to "), l.find(" line*")) {
                         let end = i + " line*".len();
-                        ranges.push((base + start, base + end));
+                        ranges.push((base + start, base + end, 0));
                         l = &l[end..];
                         base += end;
                     }
@@ -1264,7 +2571,11 @@ mod tests {
             let mut printer = SyntectPrinter::with_assets(ASSETS.clone(), stdout, opts);
             printer.print(file).unwrap();
             let printed = mem::take(printer.writer_mut()).0.into_inner();
-            let expected = read_expected_file(&expected_file);
+            assert_printed(printed, &expected_file);
+        }
+
+        fn assert_printed(printed: Vec<u8>, expected_file: &Path) {
+            let expected = read_expected_file(expected_file);
             assert_eq!(
                 printed,
                 expected,
@@ -1288,6 +2599,205 @@ mod tests {
             run_uitest(file, outfile, f);
         }
 
+        // Git changes and blames are given directly instead of running `git` since the result depends on the state
+        // of the working tree
+        fn run_git_uitest(
+            input: &str,
+            changes: Option<LineChanges>,
+            blames: Option<LineBlames>,
+            f: fn(&mut PrinterOptions<'_>) -> (),
+        ) {
+            let dir = Path::new(".").join("testdata").join("syntect");
+            let file = read_chunks(dir.join(format!("{}.rs", input)));
+            let mut opts = PrinterOptions {
+                term_width: 80,
+                color_support: TermColorSupport::True,
+                ..Default::default()
+            };
+            f(&mut opts);
+
+            let printer = SyntectPrinter::with_assets(ASSETS.clone(), DummyStdout::default(), opts);
+            let theme = printer.theme().unwrap();
+            let syntax = printer.find_syntax(&file).unwrap();
+            let mut hl = LineHighlighter::new(syntax, theme, printer.syntaxes().unwrap());
+            let mut printed = vec![];
+            Drawer::new(
+                &mut printed,
+                &printer.opts,
+                theme,
+                &file.chunks,
+                changes,
+                blames,
+            )
+            .draw_file(&file, &mut hl)
+            .unwrap();
+
+            assert_printed(printed, &dir.join(format!("{}.out", input)));
+        }
+
+        // Replaced lines are given directly by replacing each matched region with the text instead of running ripgrep
+        fn run_replace_uitest(input: &str, text: &str, f: fn(&mut PrinterOptions<'_>) -> ()) {
+            let dir = Path::new(".").join("testdata").join("syntect");
+            let mut file = read_chunks(dir.join(format!("{}.rs", input)));
+            let lines: Vec<_> = file.contents.split(|b| *b == b'\n').collect();
+            for lmat in file.line_matches.iter_mut() {
+                let line = lines[lmat.line_number as usize - 1];
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                let mut replaced = vec![];
+                let mut ranges = vec![];
+                let mut prev = 0;
+                for (start, end, pattern) in lmat.ranges.iter().copied() {
+                    replaced.extend_from_slice(&line[prev..start]);
+                    ranges.push((replaced.len(), replaced.len() + text.len(), pattern));
+                    replaced.extend_from_slice(text.as_bytes());
+                    prev = end;
+                }
+                replaced.extend_from_slice(&line[prev..]);
+
+                // Split the replaced text into lines as ripgrep::Config::replace does
+                let mut lines = vec![];
+                let mut offset = 0;
+                for line in replaced.split(|b| *b == b'\n') {
+                    let (start, end) = (offset, offset + line.len());
+                    let ranges = ranges
+                        .iter()
+                        .filter(|(s, e, _)| *s < end && start < *e)
+                        .map(|(s, e, p)| (s.max(&start) - start, e.min(&end) - start, *p))
+                        .collect();
+                    lines.push(Replacement {
+                        text: line.to_vec(),
+                        ranges,
+                    });
+                    offset = end + 1;
+                }
+                lmat.replaced = Some(lines);
+            }
+            run_uitest(file, dir.join(format!("{}.out", input)), f);
+        }
+
+        #[test]
+        fn test_replace() {
+            run_replace_uitest("replace", "*replaced*", |_| {});
+        }
+
+        #[test]
+        fn test_replace_no_grid() {
+            run_replace_uitest("replace_no_grid", "*replaced*", |o| {
+                o.grid = false;
+            });
+        }
+
+        #[test]
+        fn test_replace_multi_lines() {
+            run_replace_uitest("replace_multi_lines", "*first*\n        *second*", |_| {});
+        }
+
+        #[test]
+        fn test_replace_multi_regions() {
+            run_replace_uitest("replace_multi_regions", "\"x\"", |_| {});
+        }
+
+        #[test]
+        fn test_replace_empty() {
+            run_replace_uitest("replace_empty", "", |_| {});
+        }
+
+        #[test]
+        fn test_replace_wrap() {
+            run_replace_uitest("replace_wrap", "*replaced*", |_| {});
+        }
+
+        fn line_changes() -> LineChanges {
+            [
+                (1, LineChange::RemovedAbove),
+                (2, LineChange::Added),
+                (3, LineChange::Added),
+                (6, LineChange::Modified),
+                (9, LineChange::RemovedBelow),
+                (19, LineChange::Modified),
+            ]
+            .iter()
+            .copied()
+            .collect()
+        }
+
+        fn line_blames(lnums: impl Iterator<Item = u64>) -> LineBlames {
+            let authors = ["rhysd", "Linda_pp", "山田太郎とその仲間たち", "Uncommitted"];
+            let dates = ["3 days ago", "59 minutes ago", "11 months ago", ""];
+            lnums
+                .map(|lnum| {
+                    let i = lnum as usize % authors.len();
+                    let blame = Blame {
+                        commit: format!("{:07x}", lnum * 0x1234567 % 0xfffffff),
+                        author: authors[i].to_string(),
+                        date: dates[i].to_string(),
+                    };
+                    (lnum, blame)
+                })
+                .collect()
+        }
+
+        #[test]
+        fn test_git_changes() {
+            run_git_uitest("git_changes", Some(line_changes()), None, |_| {});
+        }
+
+        #[test]
+        fn test_git_changes_no_grid() {
+            run_git_uitest("git_changes_no_grid", Some(line_changes()), None, |o| {
+                o.grid = false;
+            });
+        }
+
+        #[test]
+        fn test_git_changes_ascii_lines() {
+            run_git_uitest("git_changes_ascii_lines", Some(line_changes()), None, |o| {
+                o.ascii_lines = true;
+            });
+        }
+
+        #[test]
+        fn test_git_changes_wrap() {
+            let changes = [(3, LineChange::Modified)].iter().copied().collect();
+            run_git_uitest("git_changes_wrap", Some(changes), None, |_| {});
+        }
+
+        #[test]
+        fn test_blame() {
+            let blames = line_blames([3, 6, 20].iter().copied());
+            run_git_uitest("blame", None, Some(blames), |_| {});
+        }
+
+        #[test]
+        fn test_blame_all_no_grid() {
+            let blames = line_blames(1..=23);
+            run_git_uitest("blame_all_no_grid", None, Some(blames), |o| {
+                o.grid = false;
+            });
+        }
+
+        #[test]
+        fn test_blame_git_changes_bg() {
+            let blames = line_blames(1..=23);
+            run_git_uitest(
+                "blame_git_changes_bg",
+                Some(line_changes()),
+                Some(blames),
+                |o| {
+                    o.background_color = true;
+                },
+            );
+        }
+
+        #[test]
+        fn test_blame_narrow() {
+            // Blame columns are dropped since they don't fit the terminal width
+            let blames = line_blames(1..=23);
+            run_git_uitest("blame_narrow", Some(line_changes()), Some(blames), |o| {
+                o.term_width = 30;
+            });
+        }
+
         macro_rules! uitests {
             ($($input:ident($f:expr),)+) => {
                 $(
@@ -1353,6 +2863,61 @@ mod tests {
             test_no_wrap_default(|o| {
                 o.text_wrap = TextWrapMode::Never;
             }),
+            test_wrap_word(|o| {
+                o.text_wrap = TextWrapMode::Word;
+            }),
+            test_wrap_word_bg(|o| {
+                o.text_wrap = TextWrapMode::Word;
+                o.background_color = true;
+            }),
+            test_wrap_word_long_token(|o| {
+                o.text_wrap = TextWrapMode::Word;
+            }),
+            test_wrap_word_indent(|o| {
+                o.text_wrap = TextWrapMode::Word;
+                o.wrap_indent = true;
+            }),
+            test_truncate(|o| {
+                o.text_wrap = TextWrapMode::Truncate;
+            }),
+            test_truncate_bg(|o| {
+                o.text_wrap = TextWrapMode::Truncate;
+                o.background_color = true;
+            }),
+            test_max_columns(|o| {
+                o.text_wrap = TextWrapMode::Truncate;
+                o.max_columns = Some(40);
+            }),
+            test_tab_stops(|_| {}),
+            test_tab_stops_8(|o| {
+                o.tab_width = 8;
+            }),
+            test_wrap_tab_stops(|_| {}),
+            test_truncate_tab_stops(|o| {
+                o.text_wrap = TextWrapMode::Truncate;
+            }),
+            test_grapheme_clusters(|_| {}),
+            test_grapheme_clusters_bg(|o| {
+                o.background_color = true;
+            }),
+            test_wrap_grapheme_clusters(|_| {}),
+            test_show_all(|o| {
+                o.show_all = true;
+            }),
+            test_show_all_bg(|o| {
+                o.show_all = true;
+                o.background_color = true;
+            }),
+            test_show_all_wrap(|o| {
+                o.show_all = true;
+            }),
+            test_show_all_truncate(|o| {
+                o.show_all = true;
+                o.text_wrap = TextWrapMode::Truncate;
+            }),
+            test_git_changes_clean(|o| {
+                o.git_changes = true;
+            }),
             test_no_wrap_no_grid(|o| {
                 o.text_wrap = TextWrapMode::Never;
                 o.grid = false;
@@ -1431,6 +2996,42 @@ mod tests {
             }),
             test_wrap_between_regions(|_| {}),
             test_wrap_regions_japanese(|_| {}),
+            test_custom_colors(|o| {
+                o.custom_colors = CustomColors {
+                    match_bg: Some(TermColor::Rgb(0x30, 0x30, 0x40)),
+                    match_lnum_fg: Some(TermColor::Ansi(214)),
+                    region_fg: Some(TermColor::Ansi(0)),
+                    region_bg: Some(TermColor::Rgb(0x5f, 0xd7, 0xff)),
+                    gutter_fg: Some(TermColor::Rgb(0x77, 0x77, 0x77)),
+                };
+            }),
+            test_custom_colors_ansi256(|o| {
+                o.color_support = TermColorSupport::Ansi256;
+                o.custom_colors = CustomColors {
+                    match_bg: Some(TermColor::Rgb(0x30, 0x30, 0x40)),
+                    match_lnum_fg: Some(TermColor::Ansi(214)),
+                    region_fg: Some(TermColor::Ansi(0)),
+                    region_bg: Some(TermColor::Rgb(0x5f, 0xd7, 0xff)),
+                    gutter_fg: Some(TermColor::Rgb(0x77, 0x77, 0x77)),
+                };
+            }),
+            test_custom_colors_ansi16(|o| {
+                o.color_support = TermColorSupport::Ansi16;
+                o.custom_colors = CustomColors {
+                    match_lnum_fg: Some(TermColor::Ansi(214)),
+                    region_fg: Some(TermColor::Ansi(0)),
+                    region_bg: Some(TermColor::Rgb(0x5f, 0xd7, 0xff)),
+                    gutter_fg: Some(TermColor::Ansi(12)),
+                    ..Default::default()
+                };
+            }),
+            test_region_style(|o| {
+                o.region_style = RegionStyle {
+                    bold: true,
+                    underline: true,
+                    inverse: true,
+                };
+            }),
         );
     }
 
@@ -1576,6 +3177,224 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_render_then_write() {
+        let opts = PrinterOptions::default();
+        let stdout = DummyStdout(RefCell::new(vec![]));
+        let mut printer = SyntectPrinter::with_assets(ASSETS.clone(), stdout, opts);
+        printer.print(sample_chunk("LICENSE.txt")).unwrap();
+        let want = mem::take(printer.writer_mut()).0.into_inner();
+
+        // Nothing is written until the rendered output is written
+        let rendered = printer.render(sample_chunk("LICENSE.txt")).unwrap();
+        assert!(printer.writer_mut().0.borrow().is_empty());
+        printer.write_rendered(rendered).unwrap();
+        let got = mem::take(printer.writer_mut()).0.into_inner();
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn test_stream_large_file() {
+        let contents = (1..=STREAM_MIN_LINES)
+            .map(|i| format!("let x{} = {};\n", i, i))
+            .collect::<String>();
+        let large = || {
+            let lmats = vec![LineMatch::lnum(1), LineMatch::lnum(STREAM_MIN_LINES)];
+            let chunks = vec![(1, STREAM_MIN_LINES)];
+            File::new(
+                PathBuf::from("large.rs"),
+                lmats,
+                chunks,
+                contents.clone().into_bytes(),
+            )
+        };
+        let opts = PrinterOptions::default();
+        let stdout = DummyStdout(RefCell::new(vec![]));
+        let mut printer = SyntectPrinter::with_assets(ASSETS.clone(), stdout, opts);
+        let want = printer.draw(&large()).unwrap();
+        assert!(!want.is_empty());
+
+        printer.print(large()).unwrap();
+        let got = mem::take(printer.writer_mut()).0.into_inner();
+        assert_eq!(got, want);
+
+        // Large files are not rendered in memory in advance
+        let rendered = printer.render(large()).unwrap();
+        assert!(matches!(rendered, Rendered::File(_)));
+        printer.write_rendered(rendered).unwrap();
+        let got = mem::take(printer.writer_mut()).0.into_inner();
+        assert_eq!(got, want);
+    }
+
+    // Rust source whose line 2 opens a block comment which is closed at the line before the last line
+    fn long_comment(lines: u64) -> String {
+        let mut s = "fn main() {}\n/*\n".to_string();
+        for i in 3..lines {
+            s.push_str(&format!("let x{} = {};\n", i, i));
+        }
+        s.push_str("*/\n");
+        s
+    }
+
+    #[test]
+    fn test_skip_lines_without_highlight() {
+        let contents = long_comment(100);
+        let syntax = ASSETS.syntax_set.find_syntax_by_name("Rust").unwrap();
+        let theme = &ASSETS.theme_set.themes["Monokai Extended"];
+        let mut highlighted = LineHighlighter::new(syntax, theme, &ASSETS.syntax_set);
+        let mut skipped = LineHighlighter::new(syntax, theme, &ASSETS.syntax_set);
+        for Line(line, lnum) in LinesInclusive::new(contents.as_bytes()) {
+            let line = str::from_utf8(line).unwrap();
+            let want = format!("{:?}", highlighted.highlight(line));
+            if lnum < 50 || 60 < lnum && lnum < 90 {
+                skipped.skip_line(line.as_bytes(), lnum, 90);
+            } else {
+                let got = format!("{:?}", skipped.highlight(line));
+                assert_eq!(got, want, "line={}", lnum);
+            }
+        }
+    }
+
+    fn deep_match(contents: &str, lnum: u64) -> File {
+        let lmats = vec![LineMatch::lnum(lnum)];
+        let chunks = vec![(lnum - 1, lnum + 1)];
+        File::new(
+            PathBuf::from("deep.rs"),
+            lmats,
+            chunks,
+            contents.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let contents = long_comment(3000);
+        let mut printer = SyntectPrinter::with_assets(
+            ASSETS.clone(),
+            DummyStdout::default(),
+            PrinterOptions::default(),
+        );
+
+        // Checkpoints are not cached by default
+        let want = printer.draw(&deep_match(&contents, 2500)).unwrap();
+        assert!(CHECKPOINTS.with(|c| c.borrow().is_empty()));
+
+        printer.cache_checkpoints = true;
+        let got = printer.draw(&deep_match(&contents, 2500)).unwrap();
+        assert_eq!(got, want);
+        let lnums = CHECKPOINTS.with(|c| {
+            let cache = c.borrow();
+            let (_, checkpoints) = cache
+                .iter()
+                .find(|(k, _)| k.2 == Path::new("deep.rs"))
+                .unwrap();
+            checkpoints.iter().map(|c| c.lnum).collect::<Vec<_>>()
+        });
+        assert_eq!(lnums, [1000, 2000]);
+
+        // Drawing again from the cached checkpoint is the same as drawing from the beginning
+        let got = printer.draw(&deep_match(&contents, 2500)).unwrap();
+        assert_eq!(got, want);
+        let got = printer.draw(&deep_match(&contents, 2900)).unwrap();
+        CHECKPOINTS.with(|c| c.borrow_mut().clear());
+        let want = printer.draw(&deep_match(&contents, 2900)).unwrap();
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn test_approx_highlight() {
+        let contents = long_comment(3000);
+        let exact = |lnum| {
+            let printer = SyntectPrinter::with_assets(
+                ASSETS.clone(),
+                DummyStdout::default(),
+                PrinterOptions::default(),
+            );
+            printer.draw(&deep_match(&contents, lnum)).unwrap()
+        };
+        let approx = |lnum| {
+            let opts = PrinterOptions {
+                approx_highlight: Some(100),
+                ..Default::default()
+            };
+            let printer = SyntectPrinter::with_assets(ASSETS.clone(), DummyStdout::default(), opts);
+            printer.draw(&deep_match(&contents, lnum)).unwrap()
+        };
+
+        // Parsing starts from the beginning when the snippet is near the beginning
+        assert_eq!(approx(50), exact(50));
+        // The block comment opened 100+ lines before is not known
+        assert_ne!(approx(2500), exact(2500));
+    }
+
+    #[test]
+    fn test_syntax_set_cache() {
+        let dir = env::temp_dir().join(format!("hgrep-syntax-cache-test-{}", process::id()));
+        let want = load_syntax_set_with_cache(None).unwrap();
+        let names = |s: &SyntaxSet| {
+            s.syntaxes()
+                .iter()
+                .map(|s| s.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // The cache is created on the first load and used on the next load
+        let got = load_syntax_set_with_cache(Some(&dir)).unwrap();
+        assert_eq!(names(&got), names(&want));
+        let path = syntax_set_cache_path(&dir);
+        assert!(path.is_file(), "path={:?}", path);
+        let got = load_syntax_set_with_cache(Some(&dir)).unwrap();
+        assert_eq!(names(&got), names(&want));
+
+        // Broken cache is replaced and caches of other versions are removed
+        fs::write(&path, b"broken").unwrap();
+        let stale = dir.join("syntaxes-0.0.0-v1-00000000.bin");
+        fs::write(&stale, b"stale").unwrap();
+        let other = dir.join("other.bin");
+        fs::write(&other, b"other").unwrap();
+        let got = load_syntax_set_with_cache(Some(&dir)).unwrap();
+        assert_eq!(names(&got), names(&want));
+        assert_ne!(fs::read(&path).unwrap(), b"broken");
+        assert!(!stale.exists());
+        assert!(other.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_syntax_set_cache_path_is_stable() {
+        let name = syntax_set_cache_path(Path::new("dir"));
+        let name = name.file_name().unwrap().to_str().unwrap();
+        let prefix = format!(
+            "syntaxes-{}-v{}-",
+            env!("CARGO_PKG_VERSION"),
+            SYNTAX_CACHE_VERSION
+        );
+        assert!(name.starts_with(&prefix), "name={:?}", name);
+        assert!(name.ends_with(".bin"), "name={:?}", name);
+    }
+
+    #[test]
+    fn test_theme_names() {
+        let mut names: Vec<_> = load_bat_themes().unwrap().themes.into_keys().collect();
+        names.sort();
+        assert_eq!(names, BAT_THEME_NAMES);
+        let mut names: Vec<_> = ThemeSet::load_defaults().themes.into_keys().collect();
+        names.sort();
+        assert_eq!(names, DEFAULT_THEME_NAMES);
+    }
+
+    #[test]
+    fn test_load_syntaxes_lazily() {
+        let printer =
+            SyntectPrinter::new(DummyStdout::default(), PrinterOptions::default()).unwrap();
+        assert!(printer.syntaxes.get().is_none());
+        assert!(printer.themes.get().is_none());
+        printer.print(sample_chunk("LICENSE.txt")).unwrap();
+        assert!(printer.syntaxes.get().is_some());
+        assert!(printer.themes.get().is_some());
+    }
+
     #[test]
     fn test_no_syntax_found() {
         let file = sample_chunk("LICENSE.txt");
@@ -1587,14 +3406,71 @@ mod tests {
         assert!(!printed.is_empty());
     }
 
+    #[test]
+    fn test_find_syntax() {
+        let printer = SyntectPrinter::with_assets(
+            ASSETS.clone(),
+            DummyStdout::default(),
+            PrinterOptions::default(),
+        );
+        let tests = [
+            ("main.rs", "fn main() {}\n", "Rust"),
+            ("main.rs.gz", "fn main() {}\n", "Rust"),
+            ("Makefile.xz", "all:\n", "Makefile"),
+            ("script", "#!/bin/bash\n", "Bourne Again Shell (bash)"),
+            ("script.zst", "#!/bin/bash\n", "Bourne Again Shell (bash)"),
+            ("unknown.gz", "foo\n", "Plain Text"),
+        ];
+        for (path, contents, want) in tests.iter() {
+            let lmats = vec![LineMatch::lnum(1)];
+            let file = File::new(
+                PathBuf::from(path),
+                lmats,
+                vec![(1, 1)],
+                contents.as_bytes().to_vec(),
+            );
+            let syntax = printer.find_syntax(&file).unwrap();
+            assert_eq!(&syntax.name, want, "path={:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_find_syntax_language() {
+        let file = File::new(
+            PathBuf::from("<stdin>"),
+            vec![LineMatch::lnum(1)],
+            vec![(1, 1)],
+            b"image: nginx\n".to_vec(),
+        );
+        for (lang, want) in [("YAML", "YAML"), ("yaml", "YAML"), ("rs", "Rust")] {
+            let opts = PrinterOptions {
+                language: Some(lang),
+                ..Default::default()
+            };
+            let printer = SyntectPrinter::with_assets(ASSETS.clone(), DummyStdout::default(), opts);
+            let syntax = printer.find_syntax(&file).unwrap();
+            assert_eq!(&syntax.name, want, "language={:?}", lang);
+        }
+
+        let opts = PrinterOptions {
+            language: Some("unknown-language"),
+            ..Default::default()
+        };
+        let printer = SyntectPrinter::with_assets(ASSETS.clone(), DummyStdout::default(), opts);
+        let err = printer.find_syntax(&file).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(
+            msg.contains("Unknown language 'unknown-language'"),
+            "message={:?}",
+            msg
+        );
+    }
+
     #[test]
     fn test_adjacent_regions() {
         let contents = b"this is test\n";
-        let ranges = (0..contents.len()).map(|i| (i, i + 1)).collect();
-        let lmats = vec![LineMatch {
-            line_number: 1,
-            ranges,
-        }];
+        let ranges = (0..contents.len()).map(|i| (i, i + 1, 0)).collect();
+        let lmats = vec![LineMatch::new(1, ranges)];
         let chunks = vec![(1, 1)];
         let file = File::new(PathBuf::from("test.txt"), lmats, chunks, contents.to_vec());
 
@@ -1622,6 +3498,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_multi_pattern_regions() {
+        let contents = b"this is test\n";
+        // Region of 'this' and ' is' are adjacent but matched to different patterns
+        let lmats = vec![LineMatch::new(1, vec![(0, 4, 0), (4, 7, 1), (8, 12, 2)])];
+        let chunks = vec![(1, 1)];
+        let file = File::new(PathBuf::from("test.txt"), lmats, chunks, contents.to_vec());
+
+        let opts = PrinterOptions {
+            color_support: TermColorSupport::True,
+            ..Default::default()
+        };
+        let stdout = DummyStdout(RefCell::new(vec![]));
+        let mut printer = SyntectPrinter::with_assets(ASSETS.clone(), stdout, opts);
+        printer.print(file).unwrap();
+
+        let printed = mem::take(printer.writer_mut()).0.into_inner();
+        let line = printed.split_inclusive(|b| *b == b'\n').nth(3).unwrap();
+        let line = str::from_utf8(line).unwrap();
+        for expected in [
+            "\x1b[48;2;255;231;146mthis",
+            "\x1b[48;2;95;215;255m is",
+            "\x1b[48;2;255;135;215mtest",
+        ] {
+            assert!(
+                line.contains(expected),
+                "{:?} is not in {:?}",
+                expected,
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_region_colors() {
+        let palette = Palette::ANSI16;
+        assert_eq!(
+            palette.region_colors(0),
+            (palette.region_fg, palette.region_bg)
+        );
+        let (fg, bg) = palette.region_colors(1);
+        assert_eq!(fg, Palette::BLACK_COLOR_16);
+        assert_eq!(bg, Palette::PATTERN_REGION_BG_16[0]);
+        // Colors are used in turn
+        assert_eq!(palette.region_colors(6), palette.region_colors(1));
+        assert_ne!(palette.region_colors(2), palette.region_colors(1));
+    }
+
     #[test]
     fn test_wrote_error_on_list_themes() {
         let opts = PrinterOptions::default();
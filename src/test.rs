@@ -11,13 +11,13 @@ pub(crate) fn read_matches<S: AsRef<str>>(dir: &Path, input: S) -> Vec<Result<Gr
         .unwrap()
         .lines()
         .enumerate()
-        .filter_map(|(idx, line)| {
-            line.ends_with('*').then(|| {
-                Ok(GrepMatch {
-                    path: path.into(),
-                    line_number: idx as u64 + 1,
-                    ranges: vec![],
-                })
+        .filter(|(_, line)| line.ends_with('*'))
+        .map(|(idx, _)| {
+            Ok(GrepMatch {
+                path: path.into(),
+                line_number: idx as u64 + 1,
+                ranges: vec![],
+                replaced: None,
             })
         })
         .collect::<Vec<Result<GrepMatch>>>()
@@ -0,0 +1,4 @@
+fn main() {
+    let password = std::env::var("APP_PASSWORD").unwrap();
+    println!("connecting with {} characters", password.len());
+}
@@ -0,0 +1,4 @@
+#!/bin/sh
+
+# Preprocessor for tests which converts the file given via stdin into upper case
+tr 'a-z' 'A-Z'
@@ -0,0 +1,23 @@
+fn main() {
+    fn foo() {
+        println!("*match to this line*");
+    }
+    fn bar() {
+        println!("*match to this line*");
+    }
+
+    foo();
+    bar();
+
+
+
+
+
+
+
+    foo();
+    bar();
+    println!("*match to this line*");
+    foo();
+    bar();
+}
@@ -0,0 +1,3 @@
+fn main() {
+  println!("*match to a line*", "*match to b line*", "*match to c line*");
+}
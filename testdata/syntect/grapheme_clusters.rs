@@ -0,0 +1,9 @@
+fn main() {
+    let flags = "🇯🇵🇺🇸🇫🇷"; // *match to flags line*
+    let skin_tones = "👍🏻👍🏽👍🏿"; // *match to skin tone line*
+    let variation = "❤️☺️✌️"; // *match to variation selector line*
+    let keycaps = "1️⃣#️⃣"; // *match to keycap line*
+    let combining = "éàö नमस्ते"; // *match to combining line*
+    let hangul = "각나"; // *match to hangul line*
+    let family = "👨‍👩‍👧‍👦"; // *match to zwj line*
+}
@@ -0,0 +1,8 @@
+fn main() {
+    let head = "*match to head line* abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij ";
+    let context = "this is not a matched line but it is long this is not a matched line but it is long this is not a matched line but it is long this is not a matched line but it is long ";
+    let middle = "abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij *match to middle line* abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij ";
+    let tail = "abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij abcdefghij *match to tail line*";
+    let wide = "あいうえおかきくけこあいうえおかきくけこあいうえおかきくけこあいうえおかきくけこ*match to wide line* さしすせそたちつてとさしすせそたちつてとさしすせそたちつてとさしすせそたちつてと";
+    let wide = "あいうえおかきくけこあいうえおかきくけこあいうえおかきくけこあいうえおかきくけこ *match to wide line* さしすせそたちつてとさしすせそたちつてとさしすせそたちつてとさしすせそたちつてと";
+}
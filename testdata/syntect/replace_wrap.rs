@@ -0,0 +1,5 @@
+// this line is soooooooooooooooooooooooooooooooooooooooooooooooooooooooooooooooooooooo loooooooooooooooooooooooooooooooooooooooooooooooooooooooooooong!!!
+fn main() {
+    println!("*match to this line* this line is soooooooooooooooooooooooooooooooooooooooooooooooooo loooooooooooooooooooooooooooooooooooooooooooooooooong!!!");
+}
+// this line is also soooooooooooooooooooooooooooooooooooooooooooooooooooooooooooo loooooooooooooooooooooooooooooooooooooooooooooooooonoooooooooog!!!
@@ -0,0 +1,10 @@
+fn main() {
+	let tab = "a	bc	def";   // *match to tab line*
+    let trailing = 1;  	  
+    let nbsp = "a b"; // *match to nbsp line*
+    let nbsp_vs16 = "a ️b"; // *match to nbsp with variation selector line*
+    let zwj = "👨‍👩‍👧"; // *match to zwj sequence line*
+    let zero_width = "a​b⁠c﻿d"; // *match to zero width line*
+    let control = "[0m"; // *match to control line*
+    let bidi = "‮abc‬"; // *match to bidi line*   
+}
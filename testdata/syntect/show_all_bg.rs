@@ -0,0 +1,8 @@
+fn main() {
+	let tab = "a	bc	def";   // *match to tab line*
+    let trailing = 1;  	  
+    let nbsp = "a b"; // *match to nbsp line*
+    let zero_width = "a​b⁠c﻿d"; // *match to zero width line*
+    let control = "[0m"; // *match to control line*
+    let bidi = "‮abc‬"; // *match to bidi line*   
+}
@@ -0,0 +1,4 @@
+fn main() {
+    let s = "*match to truncated line* a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​a​b	b	b	b	b	b	b	b	b	b	b	b	b	b	b	b	b	b	b	b	";  
+    let s = "*match to wrapped line* aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa	bbbbb​ccc ";  
+}
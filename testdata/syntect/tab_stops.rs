@@ -0,0 +1,7 @@
+struct User {
+	Name	string	`json:"name"`
+	ID	int	`json:"id"`
+	Age  	int	`json:"age"` // *match to this line*
+	Email	string	`json:"email"`
+	ab	abc	abcd	abcde	// *match to tab line*
+}
@@ -0,0 +1,3 @@
+fn main() {
+	let s = "a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	a	bc	def	*match to this line* g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	g	hi	jkl	";
+}
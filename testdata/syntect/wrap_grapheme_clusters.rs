@@ -0,0 +1,6 @@
+fn main() {
+    // Grapheme clusters crossing the wrapping point must not be split into two lines
+    let s = "*match to flags line* aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa🇯🇵🇺🇸🇫🇷 aaaaa";
+    let s = "*match to combining line* aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaééé aaaaa";
+    let s = "*match to skin tone line* aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa👍🏽👍🏽 aaaaa";
+}
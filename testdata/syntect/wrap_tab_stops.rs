@@ -0,0 +1,5 @@
+fn main() {
+    // Tab character across the wrapping point advances to the next tab stop of the line
+    let x = "abcdefghijklmnopqrstuvwxyz abcdefghijklmnopqrstuvwxyz abcdefghi	j"; // *match to this line*
+    let y = "	abc	abcdefghijklmnopqrstuvwxyz abcdefghijklmnopqrstuvwxyz	a	b	c"; // *match to tab line*
+}
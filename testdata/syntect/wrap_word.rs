@@ -0,0 +1,7 @@
+fn main() {
+    // Each of the following lines is longer than terminal width so it must be wrapped word-wise
+    println!("*match to this line* where the words should not be split between wrapped lines at all");
+    let message = "the text wrapping of this long string literal happens at a word boundary, not in the middle";
+    println!("the region which is put at the end of this line *match to this second line* spans the wrapping point");
+    let s = "日本語の文章は単語の間に空白がないため、全角文字の前後で折り返すことができます";
+}
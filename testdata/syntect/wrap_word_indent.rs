@@ -0,0 +1,8 @@
+fn main() {
+    if true {
+        if true {
+            println!("*match to this line* where continuation lines are indented as deep as the original line");
+            let message = "the text wrapping of this long string literal happens at a word boundary, and indented too";
+        }
+    }
+}
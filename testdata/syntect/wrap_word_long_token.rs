@@ -0,0 +1,4 @@
+fn main() {
+    // When a word is longer than one line, the word is wrapped character-wise as a fallback
+    println!("*match to this line* {}", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+}
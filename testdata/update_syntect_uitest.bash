@@ -40,6 +40,27 @@ COLORTERM='' "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap never              ./testdata/syntect/no_wrap_default.rs              > ./testdata/syntect/no_wrap_default.out
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap never --no-grid    ./testdata/syntect/no_wrap_no_grid.rs              > ./testdata/syntect/no_wrap_no_grid.out
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap never --background ./testdata/syntect/no_wrap_background.rs           > ./testdata/syntect/no_wrap_background.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap word               ./testdata/syntect/wrap_word.rs                    > ./testdata/syntect/wrap_word.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap word --background  ./testdata/syntect/wrap_word_bg.rs                 > ./testdata/syntect/wrap_word_bg.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap word               ./testdata/syntect/wrap_word_long_token.rs         > ./testdata/syntect/wrap_word_long_token.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap word --wrap-indent ./testdata/syntect/wrap_word_indent.rs             > ./testdata/syntect/wrap_word_indent.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap truncate           ./testdata/syntect/truncate.rs                     > ./testdata/syntect/truncate.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap truncate --background ./testdata/syntect/truncate_bg.rs                  > ./testdata/syntect/truncate_bg.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --max-columns 40          ./testdata/syntect/max_columns.rs                  > ./testdata/syntect/max_columns.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/tab_stops.rs                    > ./testdata/syntect/tab_stops.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --tab 8                   ./testdata/syntect/tab_stops_8.rs                  > ./testdata/syntect/tab_stops_8.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/wrap_tab_stops.rs               > ./testdata/syntect/wrap_tab_stops.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --wrap truncate           ./testdata/syntect/truncate_tab_stops.rs           > ./testdata/syntect/truncate_tab_stops.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/grapheme_clusters.rs            > ./testdata/syntect/grapheme_clusters.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --background              ./testdata/syntect/grapheme_clusters_bg.rs         > ./testdata/syntect/grapheme_clusters_bg.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/wrap_grapheme_clusters.rs       > ./testdata/syntect/wrap_grapheme_clusters.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --show-all                ./testdata/syntect/show_all.rs                     > ./testdata/syntect/show_all.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --show-all --background   ./testdata/syntect/show_all_bg.rs                  > ./testdata/syntect/show_all_bg.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --show-all                ./testdata/syntect/show_all_wrap.rs                > ./testdata/syntect/show_all_wrap.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --show-all --wrap truncate ./testdata/syntect/show_all_truncate.rs            > ./testdata/syntect/show_all_truncate.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --git-changes            ./testdata/syntect/git_changes_clean.rs           > ./testdata/syntect/git_changes_clean.out
+# Outputs of other git_changes*.rs and blame*.rs are not generated here since their Git changes and blames are given by unit tests directly
+# Outputs of replace*.rs are not generated here since their replaced lines are given by unit tests directly
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/multi_line_numbers.rs           > ./testdata/syntect/multi_line_numbers.out
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/multi_chunks_default.rs         > ./testdata/syntect/multi_chunks_default.out
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --no-grid                 ./testdata/syntect/multi_chunks_no_grid.rs         > ./testdata/syntect/multi_chunks_no_grid.out
@@ -80,6 +101,10 @@ COLORTERM='' "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/wrap_between_regions.rs         > ./testdata/syntect/wrap_between_regions.out
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/wrap_accross_regions.rs         > ./testdata/syntect/wrap_accross_regions.out
 "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80                           ./testdata/syntect/wrap_regions_japanese.rs        > ./testdata/syntect/wrap_regions_japanese.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --match-bg '#303040' --match-lnum-fg 214 --region-fg black --region-bg '#5fd7ff' --gutter-fg '#777'                         ./testdata/syntect/custom_colors.rs          > ./testdata/syntect/custom_colors.out
+COLORTERM='' "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --match-bg '#303040' --match-lnum-fg 214 --region-fg black --region-bg '#5fd7ff' --gutter-fg '#777'            ./testdata/syntect/custom_colors_ansi256.rs  > ./testdata/syntect/custom_colors_ansi256.out
+TERM=xterm COLORTERM='' "$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --match-lnum-fg 214 --region-fg black --region-bg '#5fd7ff' --gutter-fg bright-blue ./testdata/syntect/custom_colors_ansi16.rs > ./testdata/syntect/custom_colors_ansi16.out
+"$HGREP" '\*match to .+? line\*' -c 6 -C 6 -p syntect --term-width 80 --region-style bold,underline,inverse ./testdata/syntect/region_style.rs > ./testdata/syntect/region_style.out
 
 # Test for --list-themes
 "$HGREP" --list-themes -p syntect --term-width 80              > ./testdata/syntect/list_themes_default.out
@@ -111,6 +136,38 @@ cat ./testdata/syntect/wrap_between_bg.out
 cat ./testdata/syntect/no_wrap_default.out
 cat ./testdata/syntect/no_wrap_no_grid.out
 cat ./testdata/syntect/no_wrap_background.out
+cat ./testdata/syntect/wrap_word.out
+cat ./testdata/syntect/wrap_word_bg.out
+cat ./testdata/syntect/wrap_word_long_token.out
+cat ./testdata/syntect/wrap_word_indent.out
+cat ./testdata/syntect/truncate.out
+cat ./testdata/syntect/truncate_bg.out
+cat ./testdata/syntect/max_columns.out
+cat ./testdata/syntect/tab_stops.out
+cat ./testdata/syntect/tab_stops_8.out
+cat ./testdata/syntect/wrap_tab_stops.out
+cat ./testdata/syntect/truncate_tab_stops.out
+cat ./testdata/syntect/grapheme_clusters.out
+cat ./testdata/syntect/grapheme_clusters_bg.out
+cat ./testdata/syntect/wrap_grapheme_clusters.out
+cat ./testdata/syntect/show_all.out
+cat ./testdata/syntect/show_all_bg.out
+cat ./testdata/syntect/show_all_wrap.out
+cat ./testdata/syntect/show_all_truncate.out
+cat ./testdata/syntect/git_changes_clean.out
+cat ./testdata/syntect/git_changes.out
+cat ./testdata/syntect/git_changes_no_grid.out
+cat ./testdata/syntect/git_changes_ascii_lines.out
+cat ./testdata/syntect/git_changes_wrap.out
+cat ./testdata/syntect/blame.out
+cat ./testdata/syntect/blame_all_no_grid.out
+cat ./testdata/syntect/blame_git_changes_bg.out
+cat ./testdata/syntect/replace.out
+cat ./testdata/syntect/replace_no_grid.out
+cat ./testdata/syntect/replace_multi_lines.out
+cat ./testdata/syntect/replace_multi_regions.out
+cat ./testdata/syntect/replace_empty.out
+cat ./testdata/syntect/replace_wrap.out
 cat ./testdata/syntect/multi_line_numbers.out
 cat ./testdata/syntect/multi_chunks_default.out
 cat ./testdata/syntect/multi_chunks_no_grid.out
@@ -151,6 +208,10 @@ cat ./testdata/syntect/multi_regions_bg.out
 cat ./testdata/syntect/wrap_between_regions.out
 cat ./testdata/syntect/wrap_accross_regions.out
 cat ./testdata/syntect/wrap_regions_japanese.out
+cat ./testdata/syntect/custom_colors.out
+cat ./testdata/syntect/custom_colors_ansi256.out
+cat ./testdata/syntect/custom_colors_ansi16.out
+cat ./testdata/syntect/region_style.out
 
 cat ./testdata/syntect/list_themes_default.out
 cat ./testdata/syntect/list_themes_no_grid.out